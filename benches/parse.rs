@@ -0,0 +1,94 @@
+//! Hashing and parsing benchmarks over synthetic archives.
+
+use bsa_parser::prelude::*;
+use bsa_parser::{hash_file, hash_path, hash_path_bytes};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// Paths spread over a few hundred folders with typical asset extensions.
+fn paths(count: usize) -> Vec<String> {
+    let extensions = [".nif", ".dds", ".kf", ".wav", ".txt"];
+    (0..count)
+        .map(|i| format!("meshes\\architecture\\set{:03}\\piece_{:05}{}", i % 300, i, extensions[i % extensions.len()]))
+        .collect()
+}
+
+/// Build an in-memory v104 archive holding a small file per path.
+fn archive(paths: &[String]) -> Vec<u8> {
+    let writer: BSAWriter = paths.iter().map(|path| (path, path.as_bytes().to_vec())).collect();
+    let mut out = std::io::Cursor::new(Vec::new());
+    writer.write_to(&mut out).unwrap();
+    out.into_inner()
+}
+
+fn hashing(c: &mut Criterion) {
+    let paths = paths(10_000);
+    c.bench_function("hash_path", |b| b.iter(|| {
+        for path in &paths {
+            black_box(hash_path(black_box(path)));
+        }
+    }));
+    // the heap normalization hash_path used before stack buffers, as a baseline
+    c.bench_function("hash_path_heap", |b| b.iter(|| {
+        for path in &paths {
+            let normalized: Vec<u8> = path.bytes().map(|b| if b == b'/' { b'\\' } else { b.to_ascii_lowercase() }).collect();
+            black_box(hash_path_bytes(black_box(&normalized)));
+        }
+    }));
+    let normalized: Vec<Vec<u8>> = paths.iter().map(|path| path.to_ascii_lowercase().into_bytes()).collect();
+    c.bench_function("hash_path_bytes", |b| b.iter(|| {
+        for path in &normalized {
+            black_box(hash_path_bytes(black_box(path)));
+        }
+    }));
+    c.bench_function("hash_file", |b| b.iter(|| {
+        for path in &paths {
+            black_box(hash_file(black_box(path)));
+        }
+    }));
+}
+
+fn parsing(c: &mut Criterion) {
+    let data = archive(&paths(10_000));
+    c.bench_function("parse", |b| b.iter(|| {
+        BSAParser::from_bytes(black_box(data.clone())).parse().unwrap()
+    }));
+    let bsa = BSAParser::from_bytes(data.clone()).parse().unwrap();
+    // file records are keyed by the hash of the file name alone
+    let names: Vec<String> = paths(10_000).iter().map(|path| path.rsplit('\\').next().unwrap().to_string()).collect();
+    c.bench_function("get", |b| b.iter(|| {
+        for name in &names {
+            black_box(bsa.files.get(black_box(name)));
+        }
+    }));
+    c.bench_function("parse_sorted_index", |b| b.iter(|| {
+        BSAParser::from_bytes(black_box(data.clone())).index(bsa_parser::IndexKind::Sorted).parse().unwrap()
+    }));
+    let sorted = BSAParser::from_bytes(data.clone()).index(bsa_parser::IndexKind::Sorted).parse().unwrap();
+    c.bench_function("get_sorted_index", |b| b.iter(|| {
+        for name in &names {
+            black_box(sorted.files.get(black_box(name)));
+        }
+    }));
+}
+
+fn extraction(c: &mut Criterion) {
+    let paths = paths(10_000);
+    let bsa = BSAParser::from_bytes(archive(&paths)).parse().unwrap();
+    c.bench_function("extract", |b| b.iter(|| {
+        for path in &paths {
+            black_box(bsa.extract(path).unwrap());
+        }
+    }));
+    // one output and one scratch buffer reused across all files
+    c.bench_function("extract_into_with", |b| b.iter(|| {
+        let (mut out, mut scratch) = (Vec::new(), Vec::new());
+        for path in &paths {
+            out.clear();
+            bsa.extract_into_with(path, &mut out, &mut scratch).unwrap();
+            black_box(&out);
+        }
+    }));
+}
+
+criterion_group!(benches, hashing, parsing, extraction);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+//! Feed arbitrary bytes to the archive parser, which must fail with an error
+//! instead of panicking.
+
+#![no_main]
+
+use bsa_parser::prelude::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(archive) = BSAParser::from_bytes(data.to_vec()).parse() {
+        // extraction of every indexed file must not panic either
+        for (hash, _) in archive.files() {
+            let _ = archive.extract_by_hash(hash);
+        }
+    }
+});
@@ -0,0 +1,574 @@
+//! Bethesda Archive 2 parser, used from Fallout 4 onwards.
+//!
+//! Versions 1, 7 and 8 of Fallout 4 share one layout. Starfield versions 2
+//! and 3 extend the header: both add two u32 fields of unknown meaning, which
+//! are skipped, and version 3 adds the compression method of packed blocks.
+
+use chunk_parser::prelude::*;
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{capacity, check_bounds, to_usize, BSAParser, Error, Result};
+
+//------------------------------------------------------------------------------
+
+/// BA2 archive header.
+#[repr(C, packed)]
+#[derive(Debug)]
+pub struct BA2Header {
+    pub magic: [u8; 4],
+    pub version: u32,
+    pub archive_type: [u8; 4],
+    pub file_count: u32,
+    pub name_table_offset: u64,
+}
+
+/// General archive file record.
+#[repr(C, packed)]
+#[derive(Debug)]
+pub struct BA2FileRecord {
+    pub name_hash: u32,
+    pub ext: [u8; 4],
+    pub dir_hash: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub packed_size: u32,
+    pub unpacked_size: u32,
+    pub align: u32,
+}
+
+/// Texture archive file record, followed by `chunk_count` chunk records.
+#[repr(C, packed)]
+#[derive(Debug)]
+pub struct BA2TextureRecord {
+    pub name_hash: u32,
+    pub ext: [u8; 4],
+    pub dir_hash: u32,
+    pub unknown: u8,
+    pub chunk_count: u8,
+    pub chunk_header_size: u16,
+    pub height: u16,
+    pub width: u16,
+    pub mip_count: u8,
+    pub format: u8,
+    pub tile_mode: u16,
+}
+
+/// Texture archive mip chunk record.
+#[repr(C, packed)]
+#[derive(Debug)]
+pub struct BA2ChunkRecord {
+    pub offset: u64,
+    pub packed_size: u32,
+    pub unpacked_size: u32,
+    pub start_mip: u16,
+    pub end_mip: u16,
+    pub align: u32,
+}
+
+//------------------------------------------------------------------------------
+
+/// BA2 file properties.
+#[derive(Default)]
+pub struct BA2File {
+    pub name_hash: u32,
+    pub ext: [u8; 4],
+    pub dir_hash: u32,
+    pub offset: u64,
+    /// Size of the zlib stream, zero when the file is stored uncompressed.
+    pub packed_size: u32,
+    pub unpacked_size: u32,
+    /// File path from the name table.
+    pub name: Option<String>,
+}
+
+/// BA2 texture mip chunk properties.
+#[derive(Default)]
+pub struct BA2TextureChunk {
+    pub offset: u64,
+    /// Size of the zlib stream, zero when the chunk is stored uncompressed.
+    pub packed_size: u32,
+    pub unpacked_size: u32,
+    pub start_mip: u16,
+    pub end_mip: u16,
+}
+
+/// BA2 texture properties, the texture data is split into mip chunks.
+#[derive(Default)]
+pub struct BA2TextureEntry {
+    pub name_hash: u32,
+    pub ext: [u8; 4],
+    pub dir_hash: u32,
+    pub width: u16,
+    pub height: u16,
+    pub mip_count: u8,
+    /// DXGI format of the texture data.
+    pub format: u8,
+    pub cubemap: bool,
+    pub chunks: Vec<BA2TextureChunk>,
+    /// File path from the name table.
+    pub name: Option<String>,
+}
+
+/// Compression of packed blocks.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BA2Compression {
+    /// Zlib streams, used by all versions but Starfield version 3.
+    #[default]
+    Zlib,
+    /// LZ4 blocks, selected by method 3 of the version 3 header.
+    Lz4Block,
+}
+
+/// BA2 archive container.
+///
+/// General archives populate `files`, texture archives populate `textures`.
+pub struct BA2Archive<R> {
+    pub header: BA2Header,
+    pub compression: BA2Compression,
+    pub files: Vec<BA2File>,
+    pub textures: Vec<BA2TextureEntry>,
+    pub reader: R,
+}
+
+/// Largest expansion of a packed block, zlib streams inflate to at most about
+/// 1032 times their size and LZ4 blocks less.
+const MAX_INFLATE_RATIO: u64 = 1032;
+
+/// Compare a name table entry against a path, ignoring case and separator style.
+fn name_matches(name: &Option<String>, path: &str) -> bool {
+    match name {
+        Some(name) => name.replace('/', "\\").eq_ignore_ascii_case(&path.replace('/', "\\")),
+        None => false,
+    }
+}
+
+impl<R: Read + Seek> BA2Archive<R> {
+    /// Find a file by its path in the name table.
+    pub fn get(&self, path: &str) -> Option<&BA2File> {
+        self.files.iter().find(|file| name_matches(&file.name, path))
+    }
+
+    /// Find a texture by its path in the name table.
+    pub fn texture(&self, path: &str) -> Option<&BA2TextureEntry> {
+        self.textures.iter().find(|texture| name_matches(&texture.name, path))
+    }
+
+    /// Read a block of archive data, inflating it when it is packed.
+    ///
+    /// The block must lie within the archive and its unpacked size must be
+    /// reachable from its packed size, so forged sizes fail before allocating.
+    fn read_block(&mut self, offset: u64, packed_size: u32, unpacked_size: u32) -> Result<Vec<u8>> {
+        let length = self.reader.seek(SeekFrom::End(0))?;
+        let stored_size = if packed_size != 0 { packed_size } else { unpacked_size };
+        check_bounds(offset, stored_size as u64, length)?;
+        if packed_size != 0 && unpacked_size as u64 > packed_size as u64 * MAX_INFLATE_RATIO {
+            let message = "unpacked size exceeds what the packed block can hold";
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message).into());
+        }
+        self.reader.seek(SeekFrom::Start(offset))?;
+        if packed_size != 0 && self.compression == BA2Compression::Lz4Block {
+            let mut packed = vec![0; to_usize(packed_size)?];
+            self.reader.read_exact(&mut packed)?;
+            lz4_flex::block::decompress(&packed, to_usize(unpacked_size)?)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e).into())
+        } else if packed_size != 0 {
+            let mut data = Vec::with_capacity(to_usize(unpacked_size)?);
+            let stream = (&mut self.reader).take(packed_size as u64);
+            flate2::read::ZlibDecoder::new(stream).take(unpacked_size as u64).read_to_end(&mut data)?;
+            Ok(data)
+        } else {
+            let mut data = vec![0; to_usize(unpacked_size)?];
+            self.reader.read_exact(&mut data)?;
+            Ok(data)
+        }
+    }
+
+    /// Extract the contents of an archived file.
+    ///
+    /// Textures are reassembled into a DDS file from their mip chunks.
+    pub fn extract(&mut self, path: &str) -> Result<Vec<u8>> {
+        if let Some(file) = self.get(path) {
+            let (offset, packed_size, unpacked_size) = (file.offset, file.packed_size, file.unpacked_size);
+            return self.read_block(offset, packed_size, unpacked_size);
+        }
+
+        let texture = match self.texture(path) {
+            Some(texture) => texture,
+            None => return Err(Error::FileNotFound(path.to_string())),
+        };
+        let mut data = dds_header(texture)?;
+        let chunks: Vec<_> = texture.chunks.iter()
+            .map(|chunk| (chunk.offset, chunk.packed_size, chunk.unpacked_size))
+            .collect();
+        for (offset, packed_size, unpacked_size) in chunks {
+            data.extend(self.read_block(offset, packed_size, unpacked_size)?);
+        }
+        Ok(data)
+    }
+}
+
+//------------------------------------------------------------------------------
+
+/// Bytes per 4x4 block for block compressed DXGI formats, or per pixel otherwise.
+fn dxgi_block_size(format: u8) -> (bool, u32) {
+    match format {
+        70..=72 | 79..=81 => (true, 8), // BC1, BC4
+        73..=78 | 82..=84 | 94..=99 => (true, 16), // BC2, BC3, BC5, BC6H, BC7
+        56..=58 | 61..=65 => (false, 1), // R8
+        48..=55 | 85..=86 => (false, 2), // R8G8, B5G6R5, B5G5R5A1
+        _ => (false, 4), // R8G8B8A8, B8G8R8A8 and other 32 bit formats
+    }
+}
+
+/// Build a DDS file header with a DX10 extension describing a texture.
+///
+/// Fails with [`Error::IntegerOverflow`] when the size of the top mip does
+/// not fit the 32 bit linear size field.
+fn dds_header(texture: &BA2TextureEntry) -> Result<Vec<u8>> {
+    let (width, height) = (texture.width as u64, texture.height as u64);
+    let (compressed, size) = dxgi_block_size(texture.format);
+    let linear_size = if compressed {
+        width.div_ceil(4).max(1) * height.div_ceil(4).max(1) * size as u64
+    } else {
+        width * height * size as u64
+    };
+    let linear_size = u32::try_from(linear_size).map_err(|_| Error::IntegerOverflow)?;
+
+    let mut caps = 0x1000; // DDSCAPS_TEXTURE
+    if texture.mip_count > 1 { caps |= 0x8 | 0x400000; } // DDSCAPS_COMPLEX | DDSCAPS_MIPMAP
+    let mut caps2 = 0;
+    if texture.cubemap { caps |= 0x8; caps2 = 0xfe00; } // DDSCAPS2_CUBEMAP_ALLFACES
+
+    let mut header = Vec::with_capacity(148);
+    header.extend(b"DDS ");
+    let fields: [u32; 31] = [
+        124, // header size
+        0x1 | 0x2 | 0x4 | 0x1000 | 0x20000 | 0x80000, // caps, height, width, pixel format, mip count, linear size
+        height as u32,
+        width as u32,
+        linear_size,
+        0, // depth
+        texture.mip_count as u32,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // reserved
+        32, // pixel format size
+        0x4, // DDPF_FOURCC
+        u32::from_le_bytes(*b"DX10"),
+        0, 0, 0, 0, 0, // bit count and masks
+        caps,
+        caps2,
+        0, 0, 0, // caps3, caps4, reserved
+    ];
+    let dx10: [u32; 5] = [
+        texture.format as u32, // DXGI format
+        3, // D3D10_RESOURCE_DIMENSION_TEXTURE2D
+        if texture.cubemap { 0x4 } else { 0 }, // D3D10_RESOURCE_MISC_TEXTURECUBE
+        1, // array size
+        0, // misc flags
+    ];
+    for field in fields.iter().chain(dx10.iter()) {
+        header.extend(field.to_le_bytes());
+    }
+    Ok(header)
+}
+
+//------------------------------------------------------------------------------
+
+impl<R: Read + Seek> BSAParser<R> {
+    /// Read a u16 length prefixed name table string.
+    fn read_ba2_name(&mut self) -> Result<String> {
+        let length = self.read::<u16>()? as usize;
+        let mut name = vec![0; length];
+        self.reader().read_exact(&mut name)?;
+        String::from_utf8(name).map_err(|_| Error::InvalidUtf8)
+    }
+
+    /// Parser for the BA2 archives used in Fallout 4.
+    ///
+    /// Both the general files (`GNRL`) and texture (`DX10`) archive types are
+    /// supported.
+    pub fn ba2(mut self) -> Result<BA2Archive<R>> {
+        let header: BA2Header = self.read()?;
+        if &header.magic != b"BTDX" {
+            return Err(Error::BadMagic(header.magic));
+        }
+        let (file_count, texture_count) = match &header.archive_type {
+            b"GNRL" => (header.file_count, 0),
+            b"DX10" => (0, header.file_count),
+            _ => return Err(Error::UnsupportedArchiveType(header.archive_type)),
+        };
+
+        // starfield header extensions
+        let version = header.version;
+        if version == 2 || version == 3 {
+            let _unknown: [u32; 2] = self.read()?;
+        }
+        let compression = match version {
+            3 => match self.read::<u32>()? {
+                3 => BA2Compression::Lz4Block,
+                _ => BA2Compression::Zlib,
+            },
+            _ => BA2Compression::Zlib,
+        };
+
+        // archive length, restoring the position after the header
+        let position = self.reader().stream_position()?;
+        let remaining = self.reader().seek(SeekFrom::End(0))?.saturating_sub(position);
+        self.reader().seek(SeekFrom::Start(position))?;
+
+        let mut files = Vec::with_capacity(capacity(file_count as u64, 36, remaining));
+        for _ in 0..file_count {
+            let record: BA2FileRecord = self.read()?;
+            files.push(BA2File {
+                name_hash: record.name_hash,
+                ext: record.ext,
+                dir_hash: record.dir_hash,
+                offset: record.offset,
+                packed_size: record.packed_size,
+                unpacked_size: record.unpacked_size,
+                name: None,
+            });
+        }
+
+        let mut textures = Vec::with_capacity(capacity(texture_count as u64, 24, remaining));
+        for _ in 0..texture_count {
+            let record: BA2TextureRecord = self.read()?;
+            let mut chunks = Vec::with_capacity(record.chunk_count as usize);
+            for _ in 0..record.chunk_count {
+                let chunk: BA2ChunkRecord = self.read()?;
+                chunks.push(BA2TextureChunk {
+                    offset: chunk.offset,
+                    packed_size: chunk.packed_size,
+                    unpacked_size: chunk.unpacked_size,
+                    start_mip: chunk.start_mip,
+                    end_mip: chunk.end_mip,
+                });
+            }
+            textures.push(BA2TextureEntry {
+                name_hash: record.name_hash,
+                ext: record.ext,
+                dir_hash: record.dir_hash,
+                width: record.width,
+                height: record.height,
+                mip_count: record.mip_count,
+                format: record.format,
+                cubemap: (record.tile_mode & 0x1) != 0,
+                chunks,
+                name: None,
+            });
+        }
+
+        // name table of u16 length prefixed strings, in file record order
+        let name_table_offset = header.name_table_offset;
+        if name_table_offset != 0 {
+            self.reader().seek(SeekFrom::Start(name_table_offset))?;
+            for file in files.iter_mut() {
+                file.name = Some(self.read_ba2_name()?);
+            }
+            for texture in textures.iter_mut() {
+                texture.name = Some(self.read_ba2_name()?);
+            }
+        }
+
+        Ok(BA2Archive { header, compression, files, textures, reader: self.reader })
+    }
+}
+
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    /// Build a version 1 general archive, packing the files flagged `true`
+    /// as zlib streams.
+    fn gnrl(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        use std::io::Write;
+        let blocks: Vec<Vec<u8>> = files.iter().map(|&(_, data, packed)| match packed {
+            true => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            false => data.to_vec(),
+        }).collect();
+
+        let mut offset = 24 + 36 * files.len() as u64;
+        let name_table_offset = offset + blocks.iter().map(|block| block.len() as u64).sum::<u64>();
+        let mut data = Vec::new();
+        data.extend(b"BTDX");
+        data.extend(1u32.to_le_bytes());
+        data.extend(b"GNRL");
+        data.extend((files.len() as u32).to_le_bytes());
+        data.extend(name_table_offset.to_le_bytes());
+        // file record: hash, extension, folder hash, flags, offset, sizes, align
+        for (&(_, file, packed), block) in files.iter().zip(&blocks) {
+            data.extend([0; 16]);
+            data.extend(offset.to_le_bytes());
+            data.extend((if packed { block.len() as u32 } else { 0 }).to_le_bytes());
+            data.extend((file.len() as u32).to_le_bytes());
+            data.extend(0xbaadf00du32.to_le_bytes());
+            offset += block.len() as u64;
+        }
+        for block in &blocks {
+            data.extend(block);
+        }
+        for (name, _, _) in files {
+            data.extend((name.len() as u16).to_le_bytes());
+            data.extend(name.as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn fallout4_gnrl() -> crate::Result<()> {
+        let data = gnrl(&[("meshes\\a.nif", b"packed data", true), ("textures\\b.txt", b"stored", false)]);
+        let mut ba2 = BSAParser::from_bytes(data).ba2()?;
+        assert_eq!(ba2.compression, crate::ba2::BA2Compression::Zlib);
+        assert_eq!(ba2.files.len(), 2);
+        assert_ne!(ba2.files[0].packed_size, 0);
+        assert_eq!(ba2.files[1].packed_size, 0);
+        assert_eq!(ba2.files[1].name.as_deref(), Some("textures\\b.txt"));
+        assert_eq!(ba2.extract("Meshes/A.nif")?, b"packed data");
+        assert_eq!(ba2.extract("textures/b.txt")?, b"stored");
+        assert!(matches!(ba2.extract("c.txt"), Err(crate::Error::FileNotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn fallout4_dx10() -> crate::Result<()> {
+        use std::io::Write;
+        let mip0 = b"mip zero, packed";
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(mip0).unwrap();
+        let packed = encoder.finish().unwrap();
+        let mip1 = b"mip one";
+        let names: [&[u8]; 2] = [b"textures\\a.dds", b"textures\\cube.dds"];
+
+        // header, a texture of two chunks and a cubemap texture without chunks
+        let data_offset = 24 + 24 + 2 * 24 + 24u64;
+        let name_table_offset = data_offset + (packed.len() + mip1.len()) as u64;
+        let mut data = Vec::new();
+        data.extend(b"BTDX");
+        data.extend(1u32.to_le_bytes());
+        data.extend(b"DX10");
+        data.extend(2u32.to_le_bytes());
+        data.extend(name_table_offset.to_le_bytes());
+        // texture record: hashes, chunk count and header size, height, width,
+        // mip count, format and tile mode
+        let texture = |chunks: u8, height: u16, width: u16, mips: u8, format: u8, tile_mode: u16| {
+            let mut record = vec![0; 13];
+            record.push(chunks);
+            record.extend(24u16.to_le_bytes());
+            record.extend(height.to_le_bytes());
+            record.extend(width.to_le_bytes());
+            record.extend([mips, format]);
+            record.extend(tile_mode.to_le_bytes());
+            record
+        };
+        // chunk record: offset, sizes, mip range, align
+        let chunk = |offset: u64, packed_size: u32, unpacked_size: u32, mip: u16| {
+            let mut record = offset.to_le_bytes().to_vec();
+            record.extend(packed_size.to_le_bytes());
+            record.extend(unpacked_size.to_le_bytes());
+            record.extend(mip.to_le_bytes());
+            record.extend(mip.to_le_bytes());
+            record.extend(0xbaadf00du32.to_le_bytes());
+            record
+        };
+        data.extend(texture(2, 128, 256, 2, 71, 0)); // BC1
+        data.extend(chunk(data_offset, packed.len() as u32, mip0.len() as u32, 0));
+        data.extend(chunk(data_offset + packed.len() as u64, 0, mip1.len() as u32, 1));
+        data.extend(texture(0, 4, 4, 1, 28, 1)); // R8G8B8A8 cubemap
+        data.extend(&packed);
+        data.extend(mip1);
+        for name in names {
+            data.extend((name.len() as u16).to_le_bytes());
+            data.extend(name);
+        }
+
+        let mut ba2 = BSAParser::from_bytes(data).ba2()?;
+        assert!(ba2.files.is_empty());
+        assert_eq!(ba2.textures.len(), 2);
+        assert_eq!(ba2.textures[0].chunks.len(), 2);
+        assert!(ba2.textures[1].cubemap);
+
+        let u32_at = |dds: &[u8], offset: usize| u32::from_le_bytes(dds[offset..offset + 4].try_into().unwrap());
+        let dds = ba2.extract("textures/a.dds")?;
+        assert_eq!(&dds[..4], b"DDS ");
+        assert_eq!(u32_at(&dds, 4), 124); // header size
+        assert_eq!((u32_at(&dds, 12), u32_at(&dds, 16)), (128, 256)); // height, width
+        assert_eq!(u32_at(&dds, 20), 64 * 32 * 8); // linear size of the BC1 top mip
+        assert_eq!(u32_at(&dds, 28), 2); // mip count
+        assert_eq!(&dds[84..88], b"DX10"); // pixel format four cc
+        assert_eq!((u32_at(&dds, 108), u32_at(&dds, 112)), (0x401008, 0)); // caps, caps2
+        assert_eq!([u32_at(&dds, 128), u32_at(&dds, 132), u32_at(&dds, 136), u32_at(&dds, 140)], [71, 3, 0, 1]);
+        // chunks follow the 148 byte header in order
+        assert_eq!(&dds[148..], [&mip0[..], &mip1[..]].concat());
+
+        let dds = ba2.extract("textures/cube.dds")?;
+        assert_eq!(dds.len(), 148);
+        assert_eq!(u32_at(&dds, 20), 4 * 4 * 4);
+        assert_eq!((u32_at(&dds, 108), u32_at(&dds, 112)), (0x1008, 0xfe00));
+        assert_eq!((u32_at(&dds, 128), u32_at(&dds, 136)), (28, 0x4));
+        Ok(())
+    }
+
+    #[test]
+    fn dds_header_overflow() {
+        use super::{dds_header, BA2TextureEntry};
+        // 65535 x 65535 R8G8B8A8 pixels, 4 bytes each
+        let texture = BA2TextureEntry { width: u16::MAX, height: u16::MAX, format: 28, ..Default::default() };
+        assert!(matches!(dds_header(&texture), Err(crate::Error::IntegerOverflow)));
+        // the same size block compressed fits
+        let texture = BA2TextureEntry { format: 71, ..texture };
+        assert!(dds_header(&texture).is_ok());
+    }
+
+    #[test]
+    fn forged_sizes() {
+        // file count
+        let mut data = gnrl(&[]);
+        data[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(BSAParser::from_bytes(data).ba2().is_err());
+
+        // stored size past the end of the archive
+        let mut data = gnrl(&[("a.txt", b"a", false)]);
+        data[24 + 28..24 + 32].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut ba2 = BSAParser::from_bytes(data).ba2().unwrap();
+        assert!(matches!(ba2.extract("a.txt"), Err(crate::Error::OffsetOutOfBounds { .. })));
+
+        // unpacked size beyond the packed size
+        let mut data = gnrl(&[("a.txt", b"a", true)]);
+        data[24 + 28..24 + 32].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut ba2 = BSAParser::from_bytes(data).ba2().unwrap();
+        assert!(matches!(ba2.extract("a.txt"), Err(crate::Error::Io(_))));
+    }
+
+    #[test]
+    fn starfield_v3() -> crate::Result<()> {
+        let name = b"textures\\a.txt";
+        let mut data = Vec::new();
+        data.extend(b"BTDX");
+        data.extend(3u32.to_le_bytes());
+        data.extend(b"GNRL");
+        data.extend(1u32.to_le_bytes());
+        data.extend((24 + 12 + 36 + 3u64).to_le_bytes()); // name table offset
+        data.extend([0; 8]); // unknown
+        data.extend(3u32.to_le_bytes()); // lz4 block compression
+        // file record: hash, extension, folder hash, flags, offset, sizes, align
+        data.extend([0; 16]);
+        data.extend((24 + 12 + 36u64).to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(3u32.to_le_bytes());
+        data.extend(0xbaadf00du32.to_le_bytes());
+        data.extend(b"abc");
+        data.extend((name.len() as u16).to_le_bytes());
+        data.extend(name);
+
+        let mut ba2 = BSAParser::from_bytes(data).ba2()?;
+        assert_eq!(ba2.compression, crate::ba2::BA2Compression::Lz4Block);
+        assert_eq!(ba2.extract("textures/a.txt")?, b"abc");
+        Ok(())
+    }
+}
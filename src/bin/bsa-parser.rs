@@ -3,17 +3,82 @@
 use bsa_parser::prelude::*;
 use bsa_parser::Result;
 
+use std::path::Path;
+
 fn main() -> Result<()> {
+    // print parser warnings unless overridden by RUST_LOG
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
     // parse args
     let args: Vec<String> = std::env::args().collect();
     if args.len() < 2 {
-        println!("Usage: {} <file_path>", args[0]);
+        println!("Usage: {} <file_path> [--list | --validate | --json | <archive_path>]", args[0]);
+        println!("       {} --diff <old_path> <new_path>", args[0]);
         return Ok(())
     }
 
-    // parse file using guesser
-    let mut parser = BSAParser::file(&args[1])?;
-    parser.v104()?;
+    // compare two archives, exiting nonzero when they differ
+    if args[1] == "--diff" {
+        let (Some(old), Some(new)) = (args.get(2), args.get(3)) else {
+            eprintln!("--diff requires two archive paths");
+            std::process::exit(2);
+        };
+        let diff = BSAParser::file(old)?.parse()?.diff(&BSAParser::file(new)?.parse()?);
+        print!("{}", diff);
+        std::process::exit(if diff.is_empty() { 0 } else { 1 });
+    }
+
+    // parse file, detecting the archive version
+    #[cfg(feature = "mmap")]
+    let parser = BSAParser::mmap(&args[1])?;
+    #[cfg(not(feature = "mmap"))]
+    let parser = BSAParser::file(&args[1])?;
+    let validate = args.get(2).is_some_and(|arg| arg == "--validate");
+    let archive = match parser.validate(validate).parse() {
+        Ok(archive) => archive,
+        // report validation failures as a summary with a nonzero exit code
+        Err(e) if validate => {
+            println!("FAIL {}: {}", args[1], e);
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e),
+    };
+
+    match args.get(2).map(String::as_str) {
+        // dump all full paths, files of archives without names are listed by hash
+        Some("--list") if archive.has_names() => {
+            for path in archive.paths() {
+                println!("{}", path);
+            }
+        }
+        Some("--list") => {
+            for (hash, _) in archive.files() {
+                println!("{:#018x}", hash);
+            }
+        }
+        // dump the header and entries as json, diagnostics stay on stderr
+        #[cfg(feature = "json")]
+        Some("--json") => {
+            let mut out = std::io::stdout().lock();
+            serde_json::to_writer_pretty(&mut out, &archive).map_err(std::io::Error::other)?;
+            std::io::Write::write_all(&mut out, b"\n")?;
+        }
+        #[cfg(not(feature = "json"))]
+        Some("--json") => {
+            eprintln!("--json requires the json feature");
+            std::process::exit(2);
+        }
+        // the structure was checked while parsing
+        Some("--validate") => {
+            println!("OK {}: {} folders, {} files", args[1], archive.folder_count(), archive.file_count());
+        }
+        // extract a single file relative to the current directory
+        Some(path) => {
+            let dst = Path::new(".").join(path.replace('\\', "/"));
+            archive.extract_to(path, &dst)?;
+        }
+        None => (),
+    }
     Ok(())
 }
 
@@ -28,4 +93,73 @@ mod tests {
         cmd.arg("data/Misc.bsa");
         cmd.assert().success();
     }
+
+    #[test]
+    fn list() {
+        let mut cmd = Command::cargo_bin("bsa-parser").unwrap();
+        cmd.args(["data/Misc.bsa", "--list"]);
+        cmd.assert().success();
+    }
+
+    #[test]
+    fn list_paths() {
+        let path = std::env::temp_dir().join("bsa-parser-list.bsa");
+        let writer: bsa_parser::writer::BSAWriter = [("a/b.txt", b"b".to_vec()), ("c/d.txt", b"d".to_vec())].into_iter().collect();
+        writer.write_to(&mut std::fs::File::create(&path).unwrap()).unwrap();
+        let mut cmd = Command::cargo_bin("bsa-parser").unwrap();
+        let output = cmd.arg(&path).arg("--list").output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"a\\b.txt\nc\\d.txt\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn validate() {
+        let mut cmd = Command::cargo_bin("bsa-parser").unwrap();
+        cmd.args(["data/Misc.bsa", "--validate"]);
+        cmd.assert().success();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        let path = std::env::temp_dir().join("bsa-parser-json.bsa");
+        let writer: bsa_parser::writer::BSAWriter = [("a/b.txt", b"b".to_vec())].into_iter().collect();
+        writer.write_to(&mut std::fs::File::create(&path).unwrap()).unwrap();
+        let mut cmd = Command::cargo_bin("bsa-parser").unwrap();
+        let output = cmd.arg(&path).arg("--json").output().unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["header"]["file_count"], 1);
+        assert_eq!(json["files"][0]["name"], "b.txt");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn diff() {
+        let dir = std::env::temp_dir();
+        let (old, new) = (dir.join("bsa-parser-diff-old.bsa"), dir.join("bsa-parser-diff-new.bsa"));
+        for (path, data) in [(&old, b"b".to_vec()), (&new, b"bb".to_vec())] {
+            let writer: bsa_parser::writer::BSAWriter = [("a/b.txt", data)].into_iter().collect();
+            writer.write_to(&mut std::fs::File::create(path).unwrap()).unwrap();
+        }
+        let mut cmd = Command::cargo_bin("bsa-parser").unwrap();
+        let output = cmd.arg("--diff").arg(&old).arg(&new).output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"~ a/b.txt (1 -> 2 bytes)\n");
+        let mut cmd = Command::cargo_bin("bsa-parser").unwrap();
+        cmd.arg("--diff").arg(&old).arg(&old).assert().success();
+        std::fs::remove_file(&old).unwrap();
+        std::fs::remove_file(&new).unwrap();
+    }
+
+    #[test]
+    fn validate_truncated() {
+        let path = std::env::temp_dir().join("bsa-parser-truncated.bsa");
+        std::fs::write(&path, b"BSA\0\x68\0\0\0").unwrap();
+        let mut cmd = Command::cargo_bin("bsa-parser").unwrap();
+        cmd.arg(&path).arg("--validate");
+        cmd.assert().failure();
+        std::fs::remove_file(&path).unwrap();
+    }
 }
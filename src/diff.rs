@@ -0,0 +1,149 @@
+//! Differences between two versions of an archive.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{BSAArchive, BSAFile};
+
+/// A file found in one or both archives of a diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DiffEntry {
+    /// Hash of the folder containing the file.
+    pub folder: u64,
+    /// Hash of the file name.
+    pub hash: u64,
+    /// Full path with `/` separators, present when the archive has names.
+    pub path: Option<String>,
+    pub size: u32,
+}
+
+/// Changes from one archive to another, see [`BSAArchive::diff`].
+///
+/// Each list is sorted by folder and file hash.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ArchiveDiff {
+    /// Files only in the other archive.
+    pub added: Vec<DiffEntry>,
+    /// Files only in this archive.
+    pub removed: Vec<DiffEntry>,
+    /// Files in both archives with a different stored size, old then new.
+    pub changed: Vec<(DiffEntry, DiffEntry)>,
+}
+
+impl ArchiveDiff {
+    /// Check whether the archives hold the same files with the same sizes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for DiffEntry {
+    /// The path, or the folder and file hashes for unnamed files.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}", path),
+            None => write!(f, "{:#018x}/{:#018x}", self.folder, self.hash),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveDiff {
+    /// One line per change: `+` added, `-` removed and `~` changed sizes.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.added {
+            writeln!(f, "+ {} ({} bytes)", entry, entry.size)?;
+        }
+        for entry in &self.removed {
+            writeln!(f, "- {} ({} bytes)", entry, entry.size)?;
+        }
+        for (old, new) in &self.changed {
+            writeln!(f, "~ {} ({} -> {} bytes)", new, old.size, new.size)?;
+        }
+        Ok(())
+    }
+}
+
+impl BSAArchive {
+    /// Compare the file records of this archive against a newer version.
+    ///
+    /// Files are matched by folder and file hash so archives without names
+    /// compare as well, paths are filled in from whichever archive has them.
+    /// Sizes are the stored sizes, compressed files compare by compressed
+    /// size. File contents are not read.
+    pub fn diff(&self, other: &BSAArchive) -> ArchiveDiff {
+        let old = self.diff_entries();
+        let mut new = other.diff_entries();
+        let mut diff = ArchiveDiff::default();
+        for (key, mut entry) in old {
+            match new.remove(&key) {
+                Some(mut other) => {
+                    if entry.size != other.size {
+                        entry.path = entry.path.or(other.path.clone());
+                        other.path = other.path.or(entry.path.clone());
+                        diff.changed.push((entry, other));
+                    }
+                }
+                None => diff.removed.push(entry),
+            }
+        }
+        diff.added.extend(new.into_values());
+
+        let key = |entry: &DiffEntry| (entry.folder, entry.hash);
+        diff.added.sort_by_key(key);
+        diff.removed.sort_by_key(key);
+        diff.changed.sort_by_key(|(entry, _)| key(entry));
+        diff
+    }
+
+    /// Entries of all files including hash collisions, by folder and file hash.
+    fn diff_entries(&self) -> HashMap<(u64, u64), DiffEntry> {
+        let entry = |(hash, file): (u64, &BSAFile)| {
+            let path = self.file_path(file);
+            ((file.folder, hash), DiffEntry { folder: file.folder, hash, path, size: file.size })
+        };
+        self.files().map(entry).collect()
+    }
+}
+
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::BSAArchive;
+
+    fn archive(files: &[(&str, &[u8])], filenames: bool) -> BSAArchive {
+        BSAParser::from_bytes(crate::tests::archive_with_filenames(files, filenames)).parse().unwrap()
+    }
+
+    #[test]
+    fn diff() {
+        let old = archive(&[("a/b.txt", b"b"), ("a/c.txt", b"c"), ("d/e.txt", b"e")], true);
+        let new = archive(&[("a/b.txt", b"b"), ("a/c.txt", b"cc"), ("f/g.txt", b"g")], true);
+        let diff = old.diff(&new);
+        let paths = |entries: &[crate::diff::DiffEntry]| -> Vec<String> {
+            entries.iter().map(ToString::to_string).collect()
+        };
+        assert_eq!(paths(&diff.added), ["f/g.txt"]);
+        assert_eq!(paths(&diff.removed), ["d/e.txt"]);
+        assert_eq!(diff.changed.len(), 1);
+        let (before, after) = &diff.changed[0];
+        assert_eq!((before.path.as_deref(), before.size, after.size), (Some("a/c.txt"), 1, 2));
+        assert_eq!(diff.to_string(), "+ f/g.txt (1 bytes)\n- d/e.txt (1 bytes)\n~ a/c.txt (1 -> 2 bytes)\n");
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn diff_without_names() {
+        let old = archive(&[("a/b.txt", b"b")], true);
+        let new = archive(&[("a/b.txt", b"bb"), ("a/c.txt", b"c")], false);
+        let diff = old.diff(&new);
+        assert_eq!(diff.added[0].path, None);
+        assert_eq!(diff.added[0].hash, crate::hash_path("c.txt"));
+        assert_eq!(diff.added[0].to_string(), format!("{:#018x}/{:#018x}", crate::hash_folder("a"), crate::hash_path("c.txt")));
+        // the path is taken from the named archive
+        assert_eq!(diff.changed[0].1.path.as_deref(), Some("a/b.txt"));
+    }
+}
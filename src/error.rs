@@ -0,0 +1,125 @@
+//! Archive parser error type.
+
+use std::fmt;
+
+/// Errors produced while parsing or extracting an archive.
+#[derive(Debug)]
+pub enum Error {
+    /// Error raised by the underlying chunk parser.
+    Parser(chunk_parser::Error),
+    /// I/O error raised while reading or writing archive data.
+    Io(std::io::Error),
+    /// The requested path does not exist in the archive.
+    FileNotFound(String),
+    /// The folder of the requested path was left unread by a lazy parse, see
+    /// [`BSAArchive::load_folder`](crate::BSAArchive::load_folder).
+    FolderNotLoaded(String),
+    /// The file does not start with the archive file id.
+    BadMagic([u8; 4]),
+    /// The archive version is not handled by any parser.
+    UnsupportedVersion(u32),
+    /// A version specific parser was given an archive of another version.
+    VersionMismatch { expected: u32, found: u32 },
+    /// The header size field does not match the size of the header layout.
+    HeaderSizeMismatch { expected: u32, found: u32 },
+    /// File data uses a compression the parser cannot inflate, such as the
+    /// xbox XMem codec.
+    UnsupportedCompression,
+    /// The BA2 archive type is not handled by the parser.
+    UnsupportedArchiveType([u8; 4]),
+    /// A folder name does not match the hash of any folder record.
+    MissingFolder(u64),
+    /// The header sets archive or file flag bits the parser does not know.
+    UnknownFlags { bits: u32 },
+    /// A record hash is not greater than the hash of the record before it.
+    UnsortedRecords(u64),
+    /// A record points at data beyond the end of the archive.
+    OffsetOutOfBounds { offset: u64, size: u64 },
+    /// A size, offset or count does not fit the integer type it is converted to.
+    IntegerOverflow,
+    /// A name stored in the archive is not valid UTF-8.
+    InvalidUtf8,
+    /// A string stored in the archive is not correctly terminated.
+    MalformedString,
+    /// A nul terminated string runs to the end of the data without its nul.
+    UnterminatedString,
+    /// The operation requires folder and file names the archive does not
+    /// include, see [`BSAIndex::has_names`](crate::BSAIndex::has_names).
+    MissingNames,
+    /// The folder names read do not add up to the total folder name length
+    /// of the header, the folder blocks are out of step with the records.
+    FolderNameCountMismatch { expected: u64, found: u64 },
+    /// The file counts of the folder records do not add up to the file count
+    /// declared by the header.
+    FileCountMismatch { declared: u32, actual: u64 },
+    /// An archive path would escape the extraction destination.
+    UnsafePath(String),
+    /// The file name table does not hold one name per file record within its
+    /// declared size.
+    TruncatedNameTable,
+    /// Extracted file data does not match the expected CRC32.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// Error raised while reading the record starting at `offset`.
+    At { offset: u64, source: Box<Error> },
+}
+
+/// Result type used throughout the archive parser.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parser(e) => write!(f, "parser error: {:?}", e),
+            Error::Io(e) => write!(f, "i/o error: {}", e),
+            Error::FileNotFound(path) => write!(f, "file not found in archive: {}", path),
+            Error::FolderNotLoaded(path) => write!(f, "folder of {} not loaded yet", path),
+            Error::BadMagic(magic) => write!(f, "bad archive file id {:?}", magic),
+            Error::UnsupportedVersion(version) =>
+                write!(f, "unsupported archive version {} (supported: BSA 103, 104, 105, Morrowind 256 and BA2)", version),
+            Error::VersionMismatch { expected, found } =>
+                write!(f, "expected archive version {}, found {}", expected, found),
+            Error::HeaderSizeMismatch { expected, found } =>
+                write!(f, "header size {} does not match the {} byte header layout", found, expected),
+            Error::UnsupportedCompression => write!(f, "unsupported compression codec"),
+            Error::UnsupportedArchiveType(kind) =>
+                write!(f, "unsupported ba2 archive type {}", String::from_utf8_lossy(kind)),
+            Error::MissingFolder(hash) => write!(f, "no folder record for hash {:#018x}", hash),
+            Error::UnknownFlags { bits } => write!(f, "unknown header flag bits {:#x}", bits),
+            Error::UnsortedRecords(hash) => write!(f, "record {:#018x} is not sorted by hash", hash),
+            Error::OffsetOutOfBounds { offset, size } =>
+                write!(f, "{} bytes at offset {:#x} lie outside the archive", size, offset),
+            Error::IntegerOverflow => write!(f, "integer overflow converting size, offset or count"),
+            Error::InvalidUtf8 => write!(f, "name is not valid utf-8"),
+            Error::MalformedString => write!(f, "malformed string"),
+            Error::UnterminatedString => write!(f, "string is missing its nul terminator"),
+            Error::MissingNames => write!(f, "archive does not include folder and file names"),
+            Error::FolderNameCountMismatch { expected, found } =>
+                write!(f, "folder names take {} bytes, the header declares {}", found, expected),
+            Error::FileCountMismatch { declared, actual } =>
+                write!(f, "folder records hold {} files, the header declares {}", actual, declared),
+            Error::UnsafePath(path) => write!(f, "unsafe path in archive: {}", path),
+            Error::TruncatedNameTable => write!(f, "file name table does not match the file records"),
+            Error::ChecksumMismatch { expected, actual } =>
+                write!(f, "checksum mismatch, expected {:#010x} got {:#010x}", expected, actual),
+            Error::At { offset, source } => write!(f, "{} at offset {:#x}", source, offset),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::At { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<chunk_parser::Error> for Error {
+    fn from(e: chunk_parser::Error) -> Self { Error::Parser(e) }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self { Error::Io(e) }
+}
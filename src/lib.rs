@@ -3,56 +3,146 @@
 #![allow(non_snake_case)]
 
 use chunk_parser::prelude::*;
-pub use chunk_parser::{Error, Result};
 use esm_bindings::bsa::*;
+use log::{debug, trace, warn};
 
 use std::ffi::CString;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+mod error;
+pub use error::{Error, Result};
+
+pub mod ba2;
+pub mod diff;
+pub mod set;
+pub mod source;
+pub mod strings;
+pub mod writer;
+
+pub use source::ArchiveSource;
+
+mod tes3;
+
+#[cfg(feature = "serde")]
+mod ser;
 
 //------------------------------------------------------------------------------
 
 /// Rust native implementation of Bethesda Softworks Archive string hash.
 // https://en.uesp.net/wiki/Oblivion_Mod:Hash_Calculation
-fn tes4_hash(name: &str, ext: &str) -> u64 {
-    let mut hash: u64 = 0;
+fn tes4_hash(name: &[u8], ext: &[u8]) -> u64 {
+    let mut lo: u32 = 0; // name chars, length and extension bits
+    let mut hi: u32 = 0; // string hash of the name middle and extension
 
     if !name.is_empty() {
         let hash_bytes = [
-            *name.as_bytes().last().unwrap_or(&0), // last char or 0
-            *name.as_bytes().get(name.len() - 2).unwrap_or(&0), // second last char or 0
+            name[name.len() - 1], // last char
+            if name.len() > 2 { name[name.len() - 2] } else { 0 }, // second last char or 0
             name.len() as u8, // length
-            *name.as_bytes().first().unwrap_or(&0), // first char or 0
+            name[0], // first char
         ];
-        hash = u32::from_le_bytes(hash_bytes) as u64;
+        lo = u32::from_le_bytes(hash_bytes);
 
         if name.len() > 3 {
-            hash += str_hash(&name[1..name.len()-2]) as u64 * 0x100000000;
+            hi = str_hash(&name[1..name.len()-2]);
         }
     }
 
     if !ext.is_empty() {
-        hash += str_hash(ext) as u64 * 0x100000000;
-    
-        let i = match &ext[1..] { "nif" => 1, "kf" => 2, "dds" => 3, "wav" => 4, _ => 0 };
-    
-        if i != 0 {
-            let a = ((i & 0xfc) << 5) + ((hash & 0xff000000) >> 24) as u8;
-            let b = ((i & 0xfe) << 6) + (hash & 0x000000ff) as u8;
-            let c = (i << 7) + ((hash & 0x0000ff00) >> 8) as u8;
-    
-            hash &= !0xFF00FFFF;
-            hash += ((a as u32) << 24 | b as u32 | (c as u32) << 8) as u64;
+        hi = hi.wrapping_add(str_hash(ext));
+
+        // special extensions set the high bit of the first/last char bytes
+        lo |= match ext {
+            b".nif" => 0x00008000,
+            b".kf" => 0x00000080,
+            b".dds" => 0x00008080,
+            b".wav" => 0x80000000,
+            _ => 0,
+        };
+    }
+
+    (hi as u64) << 32 | lo as u64
+}
+
+/// Normalize a path the way the engine does before hashing, lowercasing ASCII
+/// and converting `/` separators to `\`.
+fn normalize_path(k: &str) -> String {
+    k.chars().map(|c| if c == '/' { '\\' } else { c.to_ascii_lowercase() }).collect()
+}
+
+/// Normalize a stored name like [`normalize_path`], working on the raw bytes.
+fn normalize_bytes(k: &[u8]) -> Vec<u8> {
+    k.iter().copied().map(normalize_byte).collect()
+}
+
+fn normalize_byte(b: u8) -> u8 {
+    if b == b'/' { b'\\' } else { b.to_ascii_lowercase() }
+}
+
+/// Normalize a path for hashing and pass the stored bytes to `f`.
+///
+/// ASCII paths up to `MAX_PATH` bytes, nearly every path in practice, are
+/// normalized on the stack, others are encoded and normalized on the heap.
+fn with_normalized<T>(path: &str, f: impl FnOnce(&[u8]) -> T) -> T {
+    const MAX_PATH: usize = 260;
+    if path.len() <= MAX_PATH && path.is_ascii() {
+        let mut buf = [0; MAX_PATH];
+        for (b, &c) in buf.iter_mut().zip(path.as_bytes()) {
+            *b = normalize_byte(c);
         }
+        return f(&buf[..path.len()]);
     }
+    f(&normalize_bytes(&strings::encode_name(path)))
+}
 
-    hash
+/// Hash a path already normalized and encoded as stored, without allocating.
+///
+/// The bytes must be lowercase with `\` separators, in the stored encoding.
+/// Hashes match [`hash_path`] for such paths, hot lookup loops can normalize
+/// once and hash the bytes directly.
+pub fn hash_path_bytes(path: &[u8]) -> u64 {
+    let start = path.iter().rposition(|&b| b == b'\\').map_or(0, |i| i + 1);
+    match path[start..].iter().rposition(|&b| b == b'.') {
+        Some(i) => tes4_hash(&path[..start + i], &path[start + i..]),
+        None => tes4_hash(path, b""),
+    }
+}
+
+/// Hash a path the way archive keys are hashed.
+///
+/// The path is lowercased, ASCII only, and `/` separators are converted to
+/// `\` first. The extension is split at the last `.` of the last path
+/// component and hashed separately, the rest of the path, folders included,
+/// is hashed as the name. Paths without an extension hash as folder paths.
+///
+/// Names are hashed in their stored encoding, Windows-1252 where every
+/// character can be encoded, see [`strings::encode_name`].
+pub fn hash_path(path: &str) -> u64 {
+    with_normalized(path, hash_path_bytes)
+}
+
+/// Hash a folder path as stored in folder records.
+///
+/// The path is normalized like [`hash_path`] but never split at a `.`, as
+/// folder names may contain dots. The whole directory path is hashed, not
+/// just its last component, and the archive root folder is the empty path.
+#[doc(alias = "tes4_folder_hash")]
+pub fn hash_folder(folder: &str) -> u64 {
+    with_normalized(folder, |folder| tes4_hash(folder, b""))
+}
+
+/// Hash a file name as stored in file records.
+///
+/// Only the last path component is hashed, see [`hash_path`].
+pub fn hash_file(name: &str) -> u64 {
+    hash_path(name.rsplit(['\\', '/']).next().unwrap_or(name))
 }
 
-fn str_hash(str: &str) -> u32 {
+fn str_hash(bytes: &[u8]) -> u32 {
     let mut hash: u32 = 0;
-    for &char in str.as_bytes() {
-        hash = hash.wrapping_mul(0x1003F);
-        hash += char as u32;
+    for &char in bytes {
+        hash = hash.wrapping_mul(0x1003F).wrapping_add(char as u32);
     }
     hash
 }
@@ -76,143 +166,2562 @@ impl std::hash::Hasher for BSAHasher {
     fn finish(&self) -> u64 { self.state }
 }
 
+/// Backing store of a [`BSAHashMap`].
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndexKind {
+    /// Hash map with O(1) average lookup.
+    #[default]
+    Hash,
+    /// Vector sorted by hash with O(log n) binary search lookup and much lower
+    /// per entry overhead, suited to archives with very many files.
+    Sorted,
+}
+
+enum Store<V> {
+    Hash(HashMap<u64, V, BuildHasherDefault<BSAHasher>>),
+    Sorted(Vec<(u64, V)>),
+}
+
 /// Specialised hash map for indexing TES4 hashes.
-#[derive(Default)]
-pub struct BSAHashMap<V>(HashMap<u64, V, BuildHasherDefault<BSAHasher>>);
+pub struct BSAHashMap<V>(Store<V>);
+
+impl<V> Default for BSAHashMap<V> {
+    fn default() -> Self {
+        Self::with_kind(IndexKind::Hash)
+    }
+}
 
 impl<V> BSAHashMap<V> {
+    /// Create an empty map using the given backing store.
+    pub fn with_kind(kind: IndexKind) -> Self {
+        match kind {
+            IndexKind::Hash => Self(Store::Hash(HashMap::default())),
+            IndexKind::Sorted => Self(Store::Sorted(Vec::new())),
+        }
+    }
+
     /// Insert data directly into the u64 hash index.
     ///
     /// Archive file structures in Fallout 3 index files and folders directly by
     /// the u64 hash value of the original file path.
     pub fn insert(&mut self, k: u64, v: V) {
-        self.0.insert(k, v);
+        match &mut self.0 {
+            Store::Hash(map) => { map.insert(k, v); }
+            // folder records are stored sorted, so this is normally an append
+            Store::Sorted(vec) => match vec.binary_search_by_key(&k, |e| e.0) {
+                Ok(i) => vec[i].1 = v,
+                Err(i) => vec.insert(i, (k, v)),
+            },
+        }
     }
 
     /// Retrieve data indexed by string key.
     ///
     /// Data and scripts refer to archive files and folders by their original
-    /// file path string.
+    /// file path string. File names are split at the last `.` so that the
+    /// extension is hashed separately, folder paths have no extension. Keys
+    /// are matched regardless of ASCII case and separator style.
     pub fn get(&self, k: &str) -> Option<&V> {
-        self.0.get(&tes4_hash(k, ""))
+        self.get_hash(hash_path(k))
+    }
+
+    /// Retrieve data indexed by u64 hash.
+    pub fn get_hash(&self, hash: u64) -> Option<&V> {
+        match &self.0 {
+            Store::Hash(map) => map.get(&hash),
+            Store::Sorted(vec) => vec.binary_search_by_key(&hash, |e| e.0).ok().map(|i| &vec[i].1),
+        }
+    }
+
+    /// Check whether an entry exists for a string key.
+    pub fn contains_key(&self, k: &str) -> bool {
+        self.get(k).is_some()
+    }
+
+    /// Check whether an entry exists for a u64 hash.
+    pub fn contains_hash(&self, hash: u64) -> bool {
+        self.get_hash(hash).is_some()
+    }
+
+    /// Retrieve mutable data indexed by string key.
+    pub fn get_mut(&mut self, k: &str) -> Option<&mut V> {
+        self.get_hash_mut(hash_path(k))
+    }
+
+    /// Retrieve mutable data indexed by u64 hash.
+    pub fn get_hash_mut(&mut self, hash: u64) -> Option<&mut V> {
+        match &mut self.0 {
+            Store::Hash(map) => map.get_mut(&hash),
+            Store::Sorted(vec) => match vec.binary_search_by_key(&hash, |e| e.0) {
+                Ok(i) => Some(&mut vec[i].1),
+                Err(_) => None,
+            },
+        }
+    }
+
+    /// Get the entry of a string key for in-place insertion or update, the
+    /// key is hashed once.
+    pub fn entry(&mut self, k: &str) -> Entry<'_, V> {
+        self.entry_hash(hash_path(k))
+    }
+
+    /// Get the entry of a u64 hash for in-place insertion or update.
+    pub fn entry_hash(&mut self, hash: u64) -> Entry<'_, V> {
+        if self.contains_hash(hash) {
+            Entry::Occupied(self.get_hash_mut(hash).unwrap())
+        } else {
+            Entry::Vacant(VacantEntry { map: self, hash })
+        }
+    }
+
+    /// Number of entries in the map.
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Store::Hash(map) => map.len(),
+            Store::Sorted(vec) => vec.len(),
+        }
+    }
+
+    /// Check whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over all entries and their u64 hash index, in arbitrary order
+    /// for the hash store and ascending hash order for the sorted store.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &V)> {
+        match &self.0 {
+            Store::Hash(map) => Box::new(map.iter().map(|(&k, v)| (k, v))) as Box<dyn Iterator<Item = _>>,
+            Store::Sorted(vec) => Box::new(vec.iter().map(|(k, v)| (*k, v))),
+        }
+    }
+}
+
+/// Entry of a [`BSAHashMap`], see [`BSAHashMap::entry`].
+pub enum Entry<'a, V> {
+    /// The key has a value.
+    Occupied(&'a mut V),
+    /// The key has no value yet.
+    Vacant(VacantEntry<'a, V>),
+}
+
+/// Entry of a [`BSAHashMap`] key without a value.
+pub struct VacantEntry<'a, V> {
+    map: &'a mut BSAHashMap<V>,
+    hash: u64,
+}
+
+impl<'a, V> VacantEntry<'a, V> {
+    /// Hash of the entry key.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Insert a value, returning a reference to it.
+    pub fn insert(self, v: V) -> &'a mut V {
+        match &mut self.map.0 {
+            Store::Hash(map) => map.entry(self.hash).or_insert(v),
+            Store::Sorted(vec) => {
+                let i = vec.partition_point(|e| e.0 < self.hash);
+                vec.insert(i, (self.hash, v));
+                &mut vec[i].1
+            }
+        }
+    }
+}
+
+impl<'a, V> Entry<'a, V> {
+    /// Insert `default` if the entry is vacant, returning the value.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Insert the result of `default` if the entry is vacant, returning the value.
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> &'a mut V {
+        match self {
+            Entry::Occupied(v) => v,
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Modify the value if the entry is occupied.
+    pub fn and_modify(self, f: impl FnOnce(&mut V)) -> Self {
+        match self {
+            Entry::Occupied(v) => {
+                f(v);
+                Entry::Occupied(v)
+            }
+            vacant => vacant,
+        }
+    }
+}
+
+impl<'a, V: Default> Entry<'a, V> {
+    /// Insert the default value if the entry is vacant, returning the value.
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
     }
 }
 
 //------------------------------------------------------------------------------
 
+bitflags::bitflags! {
+    /// Archive flags from the header.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct ArchiveFlags: u32 {
+        /// Folder names are stored before each folder's file records.
+        const DIRECTORY_NAMES = 0x001;
+        /// The file name table follows the file records.
+        const FILE_NAMES = 0x002;
+        /// Files are compressed by default, see [`BSAFile::compressed`].
+        const COMPRESSED = 0x004;
+        const RETAIN_DIRECTORY_NAMES = 0x008;
+        const RETAIN_FILE_NAMES = 0x010;
+        const RETAIN_FILE_NAME_OFFSETS = 0x020;
+        /// Xbox archive, everything after the header is big-endian.
+        const BIG_ENDIAN = 0x040;
+        const RETAIN_STRINGS = 0x080;
+        /// File data is prefixed with the file path, from version 104.
+        const EMBEDDED_NAMES = 0x100;
+        const XMEM_CODEC = 0x200;
+    }
+}
+
+bitflags::bitflags! {
+    /// Content categories of the archive files, from the header file flags.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct ContentFlags: u32 {
+        const MESHES = 0x001;
+        const TEXTURES = 0x002;
+        const MENUS = 0x004;
+        const SOUNDS = 0x008;
+        const VOICES = 0x010;
+        const SHADERS = 0x020;
+        const TREES = 0x040;
+        const FONTS = 0x080;
+        const MISC = 0x100;
+    }
+}
+
+/// Game an archive was built for, as far as the archive tells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Game {
+    Morrowind,
+    Oblivion,
+    /// Fallout 3 and Fallout: New Vegas.
+    Fallout3,
+    SkyrimLE,
+    SkyrimSE,
+    /// Version 104 archive without any file telling the games apart.
+    Unknown,
+}
+
+/// File extensions only found in Skyrim version 104 archives.
+const SKYRIM_EXTENSIONS: [&str; 5] = [".hkx", ".pex", ".psc", ".fuz", ".seq"];
+
+/// File extensions only found in Fallout version 104 archives.
+const FALLOUT_EXTENSIONS: [&str; 5] = [".kf", ".psa", ".spt", ".egm", ".egt"];
+
+/// Archive flag bits and their meaning.
+pub const ARCHIVE_FLAGS: [(u32, &str); 10] = [
+    (0x001, "includes directory names"),
+    (0x002, "includes filenames"),
+    (0x004, "compressed"),
+    (0x008, "retain directory names"),
+    (0x010, "retain filenames"),
+    (0x020, "retain filename offsets"),
+    (0x040, "xbox archive"),
+    (0x080, "retain strings during startup"),
+    (0x100, "embedded filenames"),
+    (0x200, "xmem codec"),
+];
+
+/// Human readable summary of a [`BSAHeader`].
+pub struct HeaderSummary<'a>(pub &'a BSAHeader);
+
+impl std::fmt::Display for HeaderSummary<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let header = self.0;
+        let (version, flags) = (header.version, header.archive_flags);
+        let name_bytes = header.total_folder_name_length as u64 + header.total_file_name_length as u64;
+        writeln!(f, "version: {}", version)?;
+        writeln!(f, "folders: {}", { header.folder_count })?;
+        writeln!(f, "files: {}", { header.file_count })?;
+        writeln!(f, "name bytes: {}", name_bytes)?;
+        write!(f, "flags: {:#x}", flags)?;
+        for (bit, name) in ARCHIVE_FLAGS {
+            if (flags & bit) != 0 {
+                write!(f, "\n  {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Folder record of version 105, the offset is widened to 64 bits.
+#[repr(C, packed)]
+#[derive(Debug)]
+pub struct BSAFolderRecordV105 {
+    pub name_hash: u64,
+    pub count: u32,
+    pub padding: u32,
+    pub offset: u64,
+}
+
 /// BSA folder properties.
-#[derive(Default)]
+///
+/// Not `Copy`, the name and file list are owned.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct BSAFolder {
     pub count: u32,
-    pub offset: u32,
+    pub offset: u64,
+    /// Folder path, present when the archive includes folder names.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "ser::cstring"))]
+    pub name: Option<CString>,
+    /// Hashes of the files in the folder, in record order.
+    pub files: Vec<u64>,
 }
 
 /// BSA file properties.
-#[derive(Default)]
+///
+/// Not `Copy`, the name is owned.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct BSAFile {
     pub size: u32,
+    /// Offset of the file data from the start of the archive, never relative
+    /// to [`BSAIndex::file_data_base`]. With embedded names the data starts
+    /// with the byte length prefixed file path.
     pub offset: u32,
+    pub compressed: bool,
+    /// Hash of the folder containing the file.
+    pub folder: u64,
+    /// File name, present when the archive includes the file name table.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "ser::cstring"))]
+    pub name: Option<CString>,
 }
 
-/// BSA archive container.
-pub struct BSAArchive {
+impl BSAFolder {
+    /// Folder of `count` files with records at `offset`, without a name.
+    pub fn new(count: u32, offset: u64) -> Self {
+        Self { count, offset, ..Default::default() }
+    }
+}
+
+impl BSAFile {
+    /// Uncompressed file of `size` bytes at `offset`, without a name or folder.
+    pub fn new(size: u32, offset: u32) -> Self {
+        Self { size, offset, ..Default::default() }
+    }
+}
+
+/// File properties returned by [`BSAIndex::stat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileInfo {
+    pub size: u32,
+    pub offset: u32,
+    pub compressed: bool,
+}
+
+/// Archive index: the header, folder and file records and names, without a
+/// source to read file data from.
+pub struct BSAIndex {
     pub header: BSAHeader,
     pub files: BSAHashMap<BSAFile>,
     pub folders: BSAHashMap<BSAFolder>,
-    pub reader: std::io::BufReader<std::fs::File>,
+    /// Folder hashes in record order.
+    order: Vec<u64>,
+    /// Files displaced from `files` by an earlier file with the same hash.
+    collisions: Vec<(u64, BSAFile)>,
+    /// Folder hashes shared by several folders.
+    folder_collisions: Vec<u64>,
+    /// Stored and computed hashes of files whose name does not match the
+    /// stored hash.
+    hash_mismatches: Vec<(u64, u64)>,
+    /// Offset of the first byte after the index.
+    data_base: u64,
+    /// Archive length, bounding the file records.
+    length: u64,
+    /// Folder blocks not read yet by a lazy parse.
+    lazy: LazyFolders,
+    /// The file name table was skipped by the parse options.
+    names_skipped: bool,
+    /// The file name table was stored zlib compressed.
+    names_compressed: bool,
 }
 
-//------------------------------------------------------------------------------
+/// Folder blocks left unread by a lazy parse, see [`BSAParser::lazy`].
+#[derive(Default)]
+struct LazyFolders {
+    /// Blocks of each unread folder: block offset, file count and index of
+    /// the first file name.
+    blocks: HashMap<u64, Vec<(u64, u32, usize)>>,
+    /// File names in record order, empty without a file name table.
+    names: Vec<CString>,
+}
 
-/// Bethesda Softworks Archive parser.
-#[chunk_parser(custom,depth,path)]
-pub struct BSAParser {}
+impl BSAIndex {
+    /// Human readable summary of the archive header.
+    pub fn summary(&self) -> HeaderSummary<'_> {
+        HeaderSummary(&self.header)
+    }
 
-impl BSAParser<std::io::BufReader<std::fs::File>> {
-    /// Read a byte sized string.
-    fn read_bzstring(&mut self) -> Result<CString> {
-        let length = self.read::<u8>()? as usize;
-        let mut v = Vec::with_capacity(length);
-        unsafe {
-            let ptr = v.as_mut_ptr();
-            self.reader().read_exact(std::slice::from_raw_parts_mut(ptr, length))?;
-            v.set_len(length-1);
-        }
-        Ok(unsafe { CString::from_vec_unchecked(v) })
+    /// Iterate over all files and their hashes, files listed by
+    /// [`BSAIndex::collisions`] last.
+    pub fn files(&self) -> impl Iterator<Item = (u64, &BSAFile)> {
+        self.files.iter().chain(self.collisions())
     }
 
-    /// Read a nul terminated string of unknown length.
-    fn read_nul_string(&mut self) -> Result<CString> {
-        let mut v = Vec::new();
-        loop {
-            let byte: u8 = self.read()?;
-            if byte == 0 { break; } // terminate at nul byte
-            v.push(byte);
-        }
-        Ok(unsafe { CString::from_vec_unchecked(v) })
+    /// Iterate over all folders and their hashes.
+    pub fn folders(&self) -> impl Iterator<Item = (u64, &BSAFolder)> {
+        self.folders.iter()
     }
 
-    /// Parser for version 104 of BSA used in Fallout 3.
-    pub fn v104(&mut self) -> Result<BSAArchive> {
-        let header: BSAHeader = self.read()?;
-        println!("{:?}", header);
+    /// Check whether the archive includes the file name table, archive flag
+    /// 0x2.
+    ///
+    /// Without it files can only be found by hash, path based operations such
+    /// as [`BSAArchive::extract_all`] fail with [`Error::MissingNames`]. False
+    /// as well when the table was skipped, see [`BSAIndex::names_skipped`].
+    pub fn has_names(&self) -> bool {
+        self.flags().contains(ArchiveFlags::FILE_NAMES) && !self.names_skipped
+    }
 
-        let mut folders = BSAHashMap::<BSAFolder>::default();
-        let mut files = BSAHashMap::<BSAFile>::default();
+    /// Check whether the file name table was stored zlib compressed as a
+    /// unit, as written by some tools, instead of as plain names.
+    pub fn names_compressed(&self) -> bool {
+        self.names_compressed
+    }
 
-        for _ in 0..header.folder_count {
-            let folder: BSAFolderRecord = self.read()?;
-            let hash = folder.name_hash;
-            folders.insert(hash, BSAFolder { count: folder.count, offset: folder.offset });
-            println!("{:?} {:#018x}", folder, hash);
+    /// Check whether the archive has a file name table the parse skipped, see
+    /// [`BSAParser::skip_names`].
+    pub fn names_skipped(&self) -> bool {
+        self.names_skipped
+    }
+
+    /// Check whether file data blocks are prefixed with the file path.
+    ///
+    /// Set by archive flag 0x100 from version 104, version 103 archives use the
+    /// bit for something else.
+    pub fn embedded_names(&self) -> bool {
+        self.header.version >= 104 && self.flags().contains(ArchiveFlags::EMBEDDED_NAMES)
+    }
+
+    /// Check whether numbers after the header are stored big-endian, as set
+    /// by archive flag 0x40 on xbox archives.
+    pub fn big_endian(&self) -> bool {
+        self.flags().contains(ArchiveFlags::BIG_ENDIAN)
+    }
+
+    /// Typed archive flags from the header.
+    pub fn flags(&self) -> ArchiveFlags {
+        ArchiveFlags::from_bits_retain(self.header.archive_flags)
+    }
+
+    /// Offset from the start of the archive where the index ends and file
+    /// data begins.
+    ///
+    /// File offsets are already absolute, this is the base for callers reading
+    /// the data block as a whole.
+    pub fn file_data_base(&self) -> u64 {
+        self.data_base
+    }
+
+    /// Offset of the first byte after the data of every file, the end of the
+    /// index for an archive without files.
+    ///
+    /// Only files read so far count, folders left unread by a lazy parse are
+    /// not included.
+    pub fn file_data_end(&self) -> u64 {
+        self.files()
+            .map(|(_, file)| file.offset as u64 + file.size as u64)
+            .fold(self.data_base, u64::max)
+    }
+
+    /// Content categories present in the archive, from the header file flags.
+    pub fn content_types(&self) -> ContentFlags {
+        ContentFlags::from_bits_retain(self.header.file_flags)
+    }
+
+    /// Detect the game the archive was built for.
+    ///
+    /// The version tells all games apart except Skyrim LE and Fallout 3/NV,
+    /// which share version 104 and zlib compression. Those are told apart by
+    /// file extensions used by one engine only: havok animations, compiled
+    /// scripts and fuz voices for Skyrim, kf animations and FaceGen data for
+    /// Fallout. Archives without names or such files are [`Game::Unknown`].
+    pub fn game(&self) -> Game {
+        let version = self.header.version;
+        match version {
+            0x100 => return Game::Morrowind,
+            103 => return Game::Oblivion,
+            105 => return Game::SkyrimSE,
+            _ => (),
+        }
+        let has = |extensions: &[&str]| self.files().any(|(_, file)| {
+            file.name.as_ref().is_some_and(|name| {
+                let name = strings::decode_name(name.as_bytes()).to_ascii_lowercase();
+                extensions.iter().any(|ext| name.ends_with(ext))
+            })
+        });
+        if has(&SKYRIM_EXTENSIONS) {
+            Game::SkyrimLE
+        } else if has(&FALLOUT_EXTENSIONS) {
+            Game::Fallout3
+        } else {
+            Game::Unknown
         }
+    }
 
-        for _ in 0..header.folder_count {
-            let name = self.read_bzstring()?;
-            let folder = folders.get(name.to_str().unwrap()).unwrap();
-            println!("{:?} {:#018x}", name, tes4_hash(name.to_str().unwrap(), ""));
-            self.push();
-            for _ in 0..folder.count {
-                let file: BSAFileRecord = self.read()?;
-                let hash = file.name_hash;
-                files.insert(hash, BSAFile { size: file.size, offset: file.offset });
-                println!("  {:?}", file);
+    /// Full `folder\\file` paths of all named files, in record order.
+    ///
+    /// Files are skipped when the archive lacks folder or file names. Names
+    /// are decoded with [`strings::decode_name`].
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = Vec::with_capacity(self.file_count());
+        for &folder_hash in &self.order {
+            let Some(folder) = self.folders.get_hash(folder_hash) else { continue };
+            let Some(folder_name) = &folder.name else { continue };
+            for file in folder.files.iter().filter_map(|&hash| self.file_in(folder_hash, hash)) {
+                if let Some(name) = &file.name {
+                    paths.push(match folder_name.as_bytes() {
+                        b"" => strings::decode_name(name.as_bytes()),
+                        _ => format!("{}\\{}", strings::decode_name(folder_name.as_bytes()), strings::decode_name(name.as_bytes())),
+                    });
+                }
             }
-            self.pop();
         }
+        paths
+    }
+
+    /// Full paths of named files starting with `prefix`, in record order.
+    ///
+    /// Paths and prefix are compared regardless of ASCII case and separator style.
+    pub fn paths_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let prefix = normalize_path(prefix);
+        let mut paths = self.paths();
+        paths.retain(|path| normalize_path(path).starts_with(&prefix));
+        paths
+    }
+
+    /// Files of a folder by folder path, in record order.
+    ///
+    /// The path is matched regardless of ASCII case and separator style.
+    pub fn folder_files(&self, folder: &str) -> Option<Vec<&BSAFile>> {
+        let folder_hash = hash_folder(folder);
+        let folder = self.folders.get_hash(folder_hash)?;
+        Some(folder.files.iter().filter_map(|&hash| self.file_in(folder_hash, hash)).collect())
+    }
+
+    /// Number of files in the archive, including files listed by
+    /// [`BSAIndex::collisions`].
+    pub fn file_count(&self) -> usize {
+        self.files.len() + self.collisions.len()
+    }
+
+    /// Number of files declared by the header.
+    pub fn declared_file_count(&self) -> u32 {
+        self.header.file_count
+    }
+
+    /// Number of file records counted by the folder records.
+    ///
+    /// Parsing fails with [`Error::FileCountMismatch`] unless this matches
+    /// [`BSAIndex::declared_file_count`]. Unlike [`BSAIndex::file_count`] it
+    /// includes files of folders not read yet by a lazy parse.
+    pub fn actual_file_count(&self) -> u64 {
+        self.folders.iter().map(|(_, folder)| folder.count as u64).sum()
+    }
+
+    /// Number of folders in the archive.
+    pub fn folder_count(&self) -> usize {
+        self.folders.len()
+    }
+
+    /// Files whose name hash collides with an earlier file, see
+    /// [`BSAArchive::extract_file`] to extract them.
+    pub fn collisions(&self) -> impl Iterator<Item = (u64, &BSAFile)> {
+        self.collisions.iter().map(|(hash, file)| (*hash, file))
+    }
+
+    /// Hashes shared by several folder records.
+    ///
+    /// Folders sharing a hash are merged into one folder holding the files of
+    /// all of them, named after the first folder read.
+    pub fn folder_collisions(&self) -> &[u64] {
+        &self.folder_collisions
+    }
+
+    /// File records whose stored hash differs from the hash of their name in
+    /// the file name table, as pairs of stored and computed hash.
+    ///
+    /// Any entry means the index is not internally consistent, lookups by path
+    /// use the computed hash and miss such files. Always empty for archives
+    /// without file names.
+    pub fn hash_mismatches(&self) -> &[(u64, u64)] {
+        &self.hash_mismatches
+    }
+
+    /// Find a file by folder and name hash, looking through the collisions
+    /// when the indexed file with that hash is in another folder.
+    fn file_in(&self, folder: u64, hash: u64) -> Option<&BSAFile> {
+        let file = self.files.get_hash(hash)?;
+        if file.folder == folder {
+            return Some(file);
+        }
+        self.collisions.iter().find(|(h, f)| *h == hash && f.folder == folder).map(|(_, file)| file)
+    }
 
-        // list of filenames delimited by nul byte
-        if (header.archive_flags & 0x2) != 0 {
-            for _ in 0..header.file_count {
-                let filename = self.read_nul_string()?;
-                println!("{:?}", filename);
-            }
+    /// Find the size, offset and compression of a file by path.
+    ///
+    /// Both the folder and the file name of the path must match, a file of
+    /// the same name in another folder is not found. Paths in folders left
+    /// unread by a lazy parse fail with [`Error::FolderNotLoaded`].
+    fn lookup(&self, path: &str) -> Result<(u32, u32, bool)> {
+        let (folder, name) = path.rsplit_once(['\\', '/']).unwrap_or(("", path));
+        let folder = hash_folder(folder);
+        match self.file_in(folder, hash_path(name)) {
+            Some(file) => Ok((file.size, file.offset, file.compressed)),
+            None if self.lazy.blocks.contains_key(&folder) => Err(Error::FolderNotLoaded(path.to_string())),
+            None => Err(Error::FileNotFound(path.to_string())),
         }
+    }
+
+    /// Look up the stored size, offset and compression of a file by path.
+    pub fn stat(&self, path: &str) -> Option<FileInfo> {
+        let (size, offset, compressed) = self.lookup(path).ok()?;
+        Some(FileInfo { size, offset, compressed })
+    }
+
+    /// Total size of the stored file data, compressed files count their
+    /// compressed size.
+    pub fn total_size(&self) -> u64 {
+        self.files().map(|(_, file)| file.size as u64).sum()
+    }
+
+    /// Full path of a file, joining the folder and file names with `/`.
+    fn file_path(&self, file: &BSAFile) -> Option<String> {
+        let folder = self.folders.get_hash(file.folder)?.name.as_ref()?;
+        let name = file.name.as_ref()?;
+        let path = match folder.as_bytes() {
+            b"" => strings::decode_name(name.as_bytes()),
+            _ => format!("{}/{}", strings::decode_name(folder.as_bytes()), strings::decode_name(name.as_bytes())),
+        };
+        Some(path.replace('\\', "/"))
+    }
+}
 
-        // now comes files...
+/// BSA archive container, file data is read from the source the archive was
+/// parsed from.
+///
+/// The archive dereferences to its [`BSAIndex`] for listing and lookups.
+pub struct BSAArchive {
+    index: BSAIndex,
+    pub source: Box<dyn ArchiveSource>,
+}
 
-        // have to reopen the reader, can't move, copy or clone without implementing BSAParser<R>
-        let reader = std::io::BufReader::new(std::fs::File::open(self.path())?);
-        Ok(BSAArchive { reader, header, folders, files })
+impl std::fmt::Debug for BSAIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BSAIndex")
+            .field("header", &self.header)
+            .field("folders", &self.folder_count())
+            .field("files", &self.file_count())
+            .field("collisions", &self.collisions.len())
+            .finish()
     }
 }
 
-//------------------------------------------------------------------------------
+/// Prints the header and entry counts, the source is skipped.
+impl std::fmt::Debug for BSAArchive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BSAArchive")
+            .field("header", &self.header)
+            .field("folders", &self.folder_count())
+            .field("files", &self.file_count())
+            .field("collisions", &self.collisions.len())
+            .finish_non_exhaustive()
+    }
+}
 
-pub mod prelude {
-    pub use chunk_parser::prelude::*;
-    pub use super::BSAParser;
+impl std::ops::Deref for BSAArchive {
+    type Target = BSAIndex;
+
+    fn deref(&self) -> &BSAIndex {
+        &self.index
+    }
 }
 
-//==============================================================================
+impl std::ops::DerefMut for BSAArchive {
+    fn deref_mut(&mut self) -> &mut BSAIndex {
+        &mut self.index
+    }
+}
 
-#[cfg(test)]
-mod tests {
-    use super::prelude::*;
+impl BSAArchive {
+    /// Open and parse an archive file of any supported version.
+    ///
+    /// The open file becomes the archive source, extraction never reopens the
+    /// path. Use [`BSAParser`] directly to set parse options or pick a version.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        BSAParser::file(path.as_ref())?.parse()
+    }
+
+    /// Extract the contents of an archived file.
+    pub fn extract(&self, path: &str) -> Result<Vec<u8>> {
+        let (size, offset, compressed) = self.lookup(path)?;
+        self.read_file(size, offset, compressed)
+    }
+
+    /// Extract the contents of an archived file by the u64 hash of its name.
+    pub fn extract_by_hash(&self, hash: u64) -> Result<Vec<u8>> {
+        match self.files.get_hash(hash) {
+            Some(file) => self.read_file(file.size, file.offset, file.compressed),
+            None => Err(Error::FileNotFound(format!("{:#018x}", hash))),
+        }
+    }
+
+    /// Read the stored bytes of an archived file without inflating them,
+    /// returning the bytes and whether they are compressed.
+    ///
+    /// Compressed data keeps its uncompressed size prefix, an embedded file
+    /// name is skipped.
+    pub fn extract_raw(&self, path: &str) -> Result<(Vec<u8>, bool)> {
+        let (size, offset, compressed) = self.lookup(path)?;
+        let (start, size) = self.data_range(size, offset)?;
+        Ok((self.source.read_at(start, to_usize(size)?)?, compressed))
+    }
+
+    /// Extract the contents of an indexed file, including files listed by
+    /// [`BSAIndex::collisions`].
+    pub fn extract_file(&self, file: &BSAFile) -> Result<Vec<u8>> {
+        self.read_file(file.size, file.offset, file.compressed)
+    }
+
+    /// Extract the contents of an archived file, checking the CRC32 of the
+    /// extracted data against `expected_crc`.
+    pub fn extract_verified(&self, path: &str, expected_crc: u32) -> Result<Vec<u8>> {
+        let data = self.extract(path)?;
+        let mut crc = flate2::Crc::new();
+        crc.update(&data);
+        match crc.sum() {
+            actual if actual == expected_crc => Ok(data),
+            actual => Err(Error::ChecksumMismatch { expected: expected_crc, actual }),
+        }
+    }
+
+    /// Stream the contents of an archived file into a writer, returning the
+    /// number of bytes written.
+    ///
+    /// Compressed files are inflated on the fly without buffering the inflated file.
+    pub fn extract_into<W: Write>(&self, path: &str, out: &mut W) -> Result<u64> {
+        self.extract_into_with(path, out, &mut Vec::new())
+    }
+
+    /// Stream the contents of an archived file into a writer like
+    /// [`BSAArchive::extract_into`], reading the stored data into `scratch`.
+    ///
+    /// Reusing one scratch buffer across calls saves an allocation per file
+    /// when extracting many small files, its contents are overwritten.
+    pub fn extract_into_with<W: Write>(&self, path: &str, out: &mut W, scratch: &mut Vec<u8>) -> Result<u64> {
+        let (size, offset, compressed) = self.lookup(path)?;
+        self.copy_file(size, offset, compressed, out, scratch)
+    }
+
+    /// Read file data, inflating it when compressed.
+    fn read_file(&self, size: u32, offset: u32, compressed: bool) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(to_usize(size)?);
+        self.copy_file(size, offset, compressed, &mut data, &mut Vec::new())?;
+        Ok(data)
+    }
+
+    /// Find the start of file data, returning the offset and size of the data.
+    ///
+    /// Offsets are widened to u64 before adding the embedded name, so data of
+    /// a file stored just below 4GB does not wrap around to the archive start.
+    fn data_range(&self, size: u32, offset: u32) -> Result<(u64, u32)> {
+        if self.embedded_names() {
+            // skip the byte length prefixed path, which is counted in the size
+            let length = self.source.read_at(offset as u64, 1)?[0] as u32;
+            return Ok((offset as u64 + 1 + length as u64, size.saturating_sub(1 + length)));
+        }
+        Ok((offset as u64, size))
+    }
+
+    /// Read the uncompressed size prefix of compressed file data.
+    fn original_size(&self, start: u64) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.source.read_at(start, 4)?);
+        Ok(if self.big_endian() { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    /// Total size of the file data once extracted.
+    ///
+    /// Reads the uncompressed size prefix of every compressed file.
+    pub fn total_uncompressed_size(&self) -> Result<u64> {
+        let mut total = 0;
+        for (_, file) in self.files() {
+            let (start, size) = self.data_range(file.size, file.offset)?;
+            total += if file.compressed { self.original_size(start)? } else { size } as u64;
+        }
+        Ok(total)
+    }
+
+    /// Read the stored file data, skipping the uncompressed size prefix of
+    /// compressed data.
+    ///
+    /// Xbox archives with the XMem codec flag fail instead of handing XMem
+    /// data to zlib.
+    #[cfg(feature = "rayon")]
+    fn read_stored(&self, size: u32, offset: u32, compressed: bool) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.read_stored_into(size, offset, compressed, &mut data)?;
+        Ok(data)
+    }
+
+    /// Read the stored file data like [`BSAArchive::read_stored`] into `buf`,
+    /// replacing its contents.
+    fn read_stored_into(&self, size: u32, offset: u32, compressed: bool, buf: &mut Vec<u8>) -> Result<()> {
+        let (start, size) = self.data_range(size, offset)?;
+        if compressed && self.flags().contains(ArchiveFlags::XMEM_CODEC) {
+            return Err(Error::UnsupportedCompression);
+        }
+        if compressed {
+            self.source.read_at_into(start + 4, to_usize(size.saturating_sub(4))?, buf)
+        } else {
+            self.source.read_at_into(start, to_usize(size)?, buf)
+        }
+    }
+
+    /// Copy file data into a writer, inflating it when compressed, with the
+    /// stored data read into `scratch`.
+    fn copy_file<W: Write>(&self, size: u32, offset: u32, compressed: bool, out: &mut W, scratch: &mut Vec<u8>) -> Result<u64> {
+        self.read_stored_into(size, offset, compressed, scratch)?;
+        if compressed {
+            Ok(inflate(self.header.version, scratch, out)?)
+        } else {
+            out.write_all(scratch)?;
+            Ok(scratch.len() as u64)
+        }
+    }
+
+    /// Extract an archived file to disk, creating any missing parent folders.
+    pub fn extract_to(&self, path: &str, dst: &Path) -> Result<()> {
+        let data = self.extract(path)?;
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(dst, data)?;
+        Ok(())
+    }
+
+    /// Extract every file to a destination folder, recreating the archive
+    /// folder structure, returning the number of files written.
+    ///
+    /// Files already present at the destination are handled as set by
+    /// `options`. Requires the archive to include folder names and the file
+    /// name table, see [`BSAIndex::has_names`]. Paths that would escape the
+    /// destination fail with [`Error::UnsafePath`] before anything is written
+    /// for that file.
+    pub fn extract_all(&self, dst: &Path, options: ExtractOptions) -> Result<usize> {
+        self.extract_all_with_progress(dst, options, |_, _| ())
+    }
+
+    /// Extract every file like [`BSAArchive::extract_all`], calling `progress`
+    /// with the number of files done and the total after each file, skipped
+    /// files included.
+    pub fn extract_all_with_progress(
+        &self,
+        dst: &Path,
+        options: ExtractOptions,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<usize> {
+        if !self.has_names() {
+            return Err(Error::MissingNames);
+        }
+        let mut entries = Vec::with_capacity(self.file_count());
+        for (_, file) in self.files() {
+            let path = self.file_path(file).ok_or(Error::MissingNames)?;
+            entries.push((path, file.size, file.offset, file.compressed));
+        }
+
+        // buffers reused across files
+        let (mut data, mut scratch) = (Vec::new(), Vec::new());
+        let total = entries.len();
+        let mut written = 0;
+        for (done, (path, size, offset, compressed)) in entries.into_iter().enumerate() {
+            if let Some(mut file) = options.open(&safe_join(dst, &path)?)? {
+                data.clear();
+                self.copy_file(size, offset, compressed, &mut data, &mut scratch)?;
+                file.write_all(&data)?;
+                written += 1;
+            }
+            progress(done + 1, total);
+        }
+        Ok(written)
+    }
+
+    /// Extract every named file into memory, keyed by its full path as listed
+    /// by [`BSAIndex::paths`].
+    ///
+    /// The whole archive is held in memory uncompressed, see
+    /// [`BSAArchive::total_uncompressed_size`] for the cost.
+    pub fn extract_all_to_map(&self) -> Result<HashMap<String, Vec<u8>>> {
+        let mut files = HashMap::with_capacity(self.file_count());
+        for path in self.paths() {
+            let data = self.extract(&path)?;
+            files.insert(path, data);
+        }
+        Ok(files)
+    }
+
+    /// Iterate over every file with its contents, reading and inflating one
+    /// file per call to `next`.
+    ///
+    /// Files are yielded sorted by name hash, files sharing a hash in record
+    /// order, keyed by their full path with `/` separators or, without names,
+    /// by their hash in hex. A file that fails to read yields an `Err` and
+    /// iteration carries on with the next file. Only the current file is held
+    /// in memory and the index is left as is.
+    pub fn drain_files(&self) -> impl Iterator<Item = Result<(String, Vec<u8>)>> + '_ {
+        let mut entries: Vec<(u64, &BSAFile)> = self.files().collect();
+        entries.sort_by_key(|&(hash, _)| hash);
+        entries.into_iter().map(move |(hash, file)| {
+            let path = self.file_path(file).unwrap_or_else(|| format!("{:#018x}", hash));
+            Ok((path, self.extract_file(file)?))
+        })
+    }
+
+    /// Export every named file into a zip archive, under its full path with
+    /// `/` separators.
+    #[cfg(feature = "zip")]
+    pub fn export_zip<W: Write + Seek>(&self, out: W) -> Result<()> {
+        let mut zip = zip::ZipWriter::new(out);
+        let options = zip::write::SimpleFileOptions::default();
+        for path in self.paths() {
+            zip.start_file(path.replace('\\', "/"), options).map_err(std::io::Error::other)?;
+            self.extract_into(&path, &mut zip)?;
+        }
+        zip.finish().map_err(std::io::Error::other)?;
+        Ok(())
+    }
+
+    /// Extract every file like [`BSAArchive::extract_all`], inflating and
+    /// writing files across the rayon thread pool.
+    ///
+    /// File data is read on the calling thread in batches, only decompression
+    /// and writing run in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn extract_all_parallel(&self, dst: &Path, options: ExtractOptions) -> Result<usize> {
+        use rayon::prelude::*;
+
+        // files read per batch, bounding the compressed data held in memory
+        const BATCH: usize = 256;
+
+        if !self.has_names() {
+            return Err(Error::MissingNames);
+        }
+
+        let mut entries = Vec::with_capacity(self.file_count());
+        for (_, file) in self.files() {
+            let path = self.file_path(file).ok_or(Error::MissingNames)?;
+            entries.push((path, file.size, file.offset, file.compressed));
+        }
+
+        // destination files are opened on the calling thread, applying the options
+        let version = self.header.version;
+        let mut written = 0;
+        for batch in entries.chunks(BATCH) {
+            let mut stored = Vec::with_capacity(batch.len());
+            for (path, size, offset, compressed) in batch {
+                let Some(file) = options.open(&safe_join(dst, path)?)? else { continue };
+                stored.push((file, self.read_stored(*size, *offset, *compressed)?, *compressed));
+            }
+            written += stored.len();
+            stored.into_par_iter().try_for_each(|(file, data, compressed)| -> std::io::Result<()> {
+                let mut out = std::io::BufWriter::new(file);
+                if compressed {
+                    inflate(version, &data, &mut out)?;
+                } else {
+                    out.write_all(&data)?;
+                }
+                out.flush()
+            })?;
+        }
+        Ok(written)
+    }
+
+    /// Borrow the data of an uncompressed file straight from an in-memory or
+    /// memory mapped source.
+    ///
+    /// Returns `None` when the file is missing, compressed or out of bounds,
+    /// or the source is not held in memory. Those files still need
+    /// [`BSAArchive::extract`].
+    pub fn file_bytes(&self, path: &str) -> Option<&[u8]> {
+        let (size, offset, compressed) = self.lookup(path).ok()?;
+        if compressed { return None; }
+        let mut data = self.source.bytes()?.get(to_usize(offset).ok()?..)?;
+        let mut size = to_usize(size).ok()?;
+        if self.embedded_names() {
+            // skip the byte length prefixed path, which is counted in the size
+            let length = 1 + *data.first()? as usize;
+            data = data.get(length..)?;
+            size = size.saturating_sub(length);
+        }
+        data.get(..size)
+    }
+
+    /// Read a folder left unread by a lazy parse, see [`BSAParser::lazy`],
+    /// and return its files like [`BSAIndex::folder_files`].
+    ///
+    /// The first load of a folder costs one seek and read of the folder block
+    /// per folder record, plus a one byte read of the folder name length.
+    /// Folders already read, and archives parsed in full, read nothing.
+    pub fn load_folder(&mut self, folder: &str) -> Result<Option<Vec<&BSAFile>>> {
+        let folder_hash = hash_folder(folder);
+        if let Some(blocks) = self.index.lazy.blocks.get(&folder_hash).cloned() {
+            for (offset, count, first) in blocks {
+                self.read_folder_block(folder_hash, offset, count, first)?;
+            }
+            self.index.lazy.blocks.remove(&folder_hash);
+        }
+        Ok(self.folder_files(folder))
+    }
+
+    /// Read the bytes after the data of the last file to the end of the
+    /// archive, as appended by some tools. Empty when there are none.
+    ///
+    /// Folders left unread by a lazy parse are read first, the last file may
+    /// be in any of them.
+    pub fn trailing_data(&mut self) -> Result<Vec<u8>> {
+        let blocks: Vec<_> = self.index.lazy.blocks.drain().collect();
+        for (folder_hash, blocks) in blocks {
+            for (offset, count, first) in blocks {
+                self.read_folder_block(folder_hash, offset, count, first)?;
+            }
+        }
+        let end = self.file_data_end();
+        let size = self.length.saturating_sub(end);
+        self.source.read_at(end, to_usize(size)?)
+    }
+
+    /// Read a folder block, the folder name and file records, into the index.
+    fn read_folder_block(&mut self, folder_hash: u64, offset: u64, count: u32, first: usize) -> Result<()> {
+        let name_length = self.source.read_at(offset, 1)?[0] as u64;
+        let size = 1 + name_length + 16 * count as u64;
+        check_bounds(offset, size, self.index.length)?;
+        let block = self.source.read_at(offset, to_usize(size)?)?;
+        let mut reader = &block[..];
+        let name = strings::read_bzstring(&mut reader)?;
+        if tes4_hash(&normalize_bytes(name.as_bytes()), b"") != folder_hash {
+            return Err(Error::MissingFolder(folder_hash));
+        }
+
+        let big_endian = self.big_endian();
+        let compressed = self.flags().contains(ArchiveFlags::COMPRESSED);
+        let mut hashes = Vec::with_capacity(to_usize(count)?);
+        for (i, record) in reader.chunks_exact(16).enumerate() {
+            let hash = u64::from_le_bytes(record[..8].try_into().unwrap());
+            let size = u32::from_le_bytes(record[8..12].try_into().unwrap());
+            let offset = u32::from_le_bytes(record[12..].try_into().unwrap());
+            let (hash, size, offset) = match big_endian {
+                true => (hash.swap_bytes(), size.swap_bytes(), offset.swap_bytes()),
+                false => (hash, size, offset),
+            };
+            let compressed = compressed ^ ((size & 0x40000000) != 0);
+            let size = size & 0x3fffffff;
+            check_bounds(offset as u64, size as u64, self.index.length)?;
+            let name = self.index.lazy.names.get(first + i).cloned();
+            if let Some(name) = &name {
+                check_name_hash(hash, name, &mut self.index.hash_mismatches);
+            }
+            let file = BSAFile { size, offset, compressed, folder: folder_hash, name };
+            if self.index.files.contains_hash(hash) {
+                self.index.collisions.push((hash, file));
+            } else {
+                self.index.files.insert(hash, file);
+            }
+            hashes.push(hash);
+        }
+
+        if let Some(folder) = self.index.folders.get_hash_mut(folder_hash) {
+            folder.name.get_or_insert(name);
+            folder.files.extend(hashes);
+        }
+        Ok(())
+    }
+
+    /// Drop the source, keeping only the index.
+    pub fn into_index(self) -> BSAIndex {
+        self.index
+    }
+}
+
+/// Inflate compressed file data into a writer, LZ4 frames from version 105
+/// and zlib before.
+fn inflate<W: Write>(version: u32, data: &[u8], out: &mut W) -> std::io::Result<u64> {
+    if version >= 105 {
+        std::io::copy(&mut lz4_flex::frame::FrameDecoder::new(data), out)
+    } else {
+        std::io::copy(&mut flate2::read::ZlibDecoder::new(data), out)
+    }
+}
+
+impl TryFrom<&Path> for BSAArchive {
+    type Error = Error;
+
+    fn try_from(path: &Path) -> Result<Self> {
+        Self::open(path)
+    }
+}
+
+//------------------------------------------------------------------------------
+
+/// Options controlling how an archive is parsed.
+#[derive(Default, Clone, Copy)]
+pub struct ParseOptions {
+    /// Check that folder and file records are sorted ascending by hash.
+    pub validate: bool,
+    /// Backing store of the folder and file indexes.
+    pub index: IndexKind,
+    /// Reject archive and file flag bits the parser does not know.
+    pub strict: bool,
+    /// Defer reading folder blocks until a folder is loaded.
+    pub lazy: bool,
+    /// Skip the file name table, leaving files unnamed.
+    pub skip_names: bool,
+}
+
+/// Options controlling how extraction treats files already present at the
+/// destination, by default extraction fails on an existing file.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExtractOptions {
+    /// Replace existing files.
+    pub overwrite: bool,
+    /// Leave existing files as they are and carry on with the next file,
+    /// taking precedence over `overwrite`.
+    pub skip_existing: bool,
+}
+
+impl ExtractOptions {
+    /// Open a destination file for writing, creating missing parent folders.
+    ///
+    /// Returns `None` for an existing file that is skipped, an existing file
+    /// neither skipped nor overwritten fails with
+    /// [`std::io::ErrorKind::AlreadyExists`].
+    fn open(&self, dst: &Path) -> std::io::Result<Option<std::fs::File>> {
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut open = std::fs::OpenOptions::new();
+        match self.overwrite && !self.skip_existing {
+            true => open.write(true).create(true).truncate(true),
+            false => open.write(true).create_new(true),
+        };
+        match open.open(dst) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && self.skip_existing => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Join an archive path onto an extraction destination, rejecting paths that
+/// could escape it.
+///
+/// Archive paths are untrusted, `..` components and drive or stream
+/// separators fail with [`Error::UnsafePath`]. Empty and `.` components are
+/// dropped, so a leading separator stays inside the destination.
+fn safe_join(dst: &Path, path: &str) -> Result<std::path::PathBuf> {
+    let mut joined = dst.to_path_buf();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => return Err(Error::UnsafePath(path.to_string())),
+            part if part.contains(':') => return Err(Error::UnsafePath(path.to_string())),
+            part => joined.push(part),
+        }
+    }
+    Ok(joined)
+}
+
+/// Split a file name table into its nul terminated names, `None` unless it
+/// holds exactly `count` names.
+fn split_names(block: &[u8], count: usize) -> Option<Vec<&[u8]>> {
+    let names: Vec<&[u8]> = match block.split_last() {
+        Some((&0, names)) => names.split(|&byte| byte == 0).collect(),
+        Some(_) => return None,
+        None => Vec::new(),
+    };
+    (names.len() == count).then_some(names)
+}
+
+/// Check for a zlib stream header: deflate method and a valid check value.
+fn is_zlib(data: &[u8]) -> bool {
+    match data {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && u16::from_be_bytes([*cmf, *flg]) % 31 == 0,
+        _ => false,
+    }
+}
+
+/// Check a record hash is greater than the previous record hash.
+fn check_sorted(previous: &mut Option<u64>, hash: u64) -> Result<()> {
+    if previous.is_some_and(|previous| hash <= previous) {
+        return Err(Error::UnsortedRecords(hash));
+    }
+    *previous = Some(hash);
+    Ok(())
+}
+
+/// Index file records by hash, setting aside files whose hash is already
+/// taken instead of overwriting the earlier file.
+///
+/// The sorted store is built with one sort instead of an insert per record,
+/// records are only sorted within their folder.
+fn index_files(mut records: Vec<(u64, BSAFile)>, kind: IndexKind) -> (BSAHashMap<BSAFile>, Vec<(u64, BSAFile)>) {
+    let mut collisions = Vec::new();
+    if kind == IndexKind::Sorted {
+        // a stable sort keeps files sharing a hash in record order
+        records.sort_by_key(|&(hash, _)| hash);
+        let mut sorted: Vec<(u64, BSAFile)> = Vec::with_capacity(records.len());
+        for (hash, file) in records {
+            if sorted.last().is_some_and(|&(last, _)| last == hash) {
+                debug!("hash collision {:#018x} {:?}", hash, file.name);
+                collisions.push((hash, file));
+            } else {
+                sorted.push((hash, file));
+            }
+        }
+        return (BSAHashMap(Store::Sorted(sorted)), collisions);
+    }
+    let mut files = BSAHashMap::with_kind(kind);
+    for (hash, file) in records {
+        if files.contains_hash(hash) {
+            debug!("hash collision {:#018x} {:?}", hash, file.name);
+            collisions.push((hash, file));
+        } else {
+            files.insert(hash, file);
+        }
+    }
+    (files, collisions)
+}
+
+/// Check a stored file record hash against the hash of its file name,
+/// recording a mismatch.
+fn check_name_hash(hash: u64, name: &CString, mismatches: &mut Vec<(u64, u64)>) {
+    let computed = hash_path_bytes(&normalize_bytes(name.as_bytes()));
+    if computed != hash {
+        warn!("file {:?} hash {:#018x} does not match its name hash {:#018x}", name, hash, computed);
+        mismatches.push((hash, computed));
+    }
+}
+
+/// Convert a size, offset or count to `usize`, failing instead of truncating.
+pub(crate) fn to_usize<T: TryInto<usize>>(value: T) -> Result<usize> {
+    value.try_into().map_err(|_| Error::IntegerOverflow)
+}
+
+/// Check a block of `size` bytes at `offset` lies within an archive of `length` bytes.
+fn check_bounds(offset: u64, size: u64, length: u64) -> Result<()> {
+    match offset.checked_add(size) {
+        Some(end) if end <= length => Ok(()),
+        _ => Err(Error::OffsetOutOfBounds { offset, size }),
+    }
+}
+
+/// Capacity to reserve for `count` records of `size` bytes, at most as many
+/// as fit in the `remaining` bytes so a forged count cannot exhaust memory.
+pub(crate) fn capacity(count: u64, size: u64, remaining: u64) -> usize {
+    count.min(remaining / size) as usize
+}
+
+/// Bethesda Softworks Archive parser.
+#[chunk_parser(custom,depth,path)]
+pub struct BSAParser {
+    options: ParseOptions,
+}
+
+impl BSAParser<std::io::Cursor<Vec<u8>>> {
+    /// Create a parser over an archive held in memory.
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self::new(std::io::Cursor::new(data))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl BSAParser<std::io::Cursor<memmap2::Mmap>> {
+    /// Create a parser over a memory mapped archive file.
+    ///
+    /// Extraction copies straight out of the map instead of going through
+    /// buffered seeks. The file must not be modified while it is mapped.
+    pub fn mmap<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Self::new(std::io::Cursor::new(map)))
+    }
+}
+
+impl<R: Read + Seek> BSAParser<R> {
+    /// Enable validation of the record order required by the format.
+    ///
+    /// Validation fails with [`Error::UnsortedRecords`] when folder records, or
+    /// the file records of a folder, are not sorted ascending by hash.
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.options.validate = validate;
+        self
+    }
+
+    /// Enable strict mode, rejecting unknown header flags.
+    ///
+    /// Strict parsing fails with [`Error::UnknownFlags`] when the header sets
+    /// archive or file flag bits the parser does not know, otherwise they are
+    /// logged and ignored.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Enable lazy parsing of folder contents.
+    ///
+    /// A lazy parse reads the header, folder records and file name table and
+    /// skips the folder blocks holding folder names and file records. Folders
+    /// are listed with their record counts but hold no files, and files are
+    /// missing from the index, until read with [`BSAArchive::load_folder`].
+    /// Extracting a file of a folder not read yet fails with
+    /// [`Error::FolderNotLoaded`].
+    /// Version 103 to 105 archives only, Morrowind archives are always read
+    /// in full.
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.options.lazy = lazy;
+        self
+    }
+
+    /// Skip the file name table instead of reading a name per file.
+    ///
+    /// Files are indexed by hash only, which is all lookups need, saving the
+    /// name allocations when scanning many archives. The archive reports
+    /// [`BSAIndex::has_names`] false and [`BSAIndex::names_skipped`] true.
+    /// Version 103 to 105 archives only, Morrowind names are part of the
+    /// file list.
+    pub fn skip_names(mut self, skip_names: bool) -> Self {
+        self.options.skip_names = skip_names;
+        self
+    }
+
+    /// Select the backing store of the parsed folder and file indexes.
+    pub fn index(mut self, index: IndexKind) -> Self {
+        self.options.index = index;
+        self
+    }
+
+    /// Current position of the underlying reader.
+    ///
+    /// Parsing consumes the parser, so a completed parse reports where the
+    /// index ended, and file data begins, through
+    /// [`BSAIndex::file_data_base`] instead.
+    pub fn position(&mut self) -> Result<u64> {
+        Ok(self.reader().stream_position()?)
+    }
+
+    /// Read a byte sized string, see [`strings::read_bzstring`].
+    fn read_bzstring(&mut self) -> Result<CString> {
+        strings::read_bzstring(self.reader())
+    }
+
+    /// Read the archive header, failing unless it has the `expected` version.
+    fn read_version(&mut self, expected: u32) -> Result<BSAHeader> {
+        let header = self.at(Self::read_header)?;
+        let found = header.version;
+        if found != expected {
+            return Err(Error::VersionMismatch { expected, found });
+        }
+        Ok(header)
+    }
+
+    /// Read the file name table of `size` bytes at the current position,
+    /// holding `count` nul terminated names, and whether it was compressed.
+    ///
+    /// Some tools zlib compress the table as a unit. A table that does not
+    /// split into `count` names but starts with a zlib header is inflated and
+    /// split again, inflating no more than `count` names of the longest
+    /// stored length could take.
+    fn read_file_names(&mut self, size: u32, count: usize, length: u64) -> Result<(Vec<CString>, bool)> {
+        check_bounds(self.reader().stream_position()?, size as u64, length)?;
+        let mut block = vec![0; to_usize(size)?];
+        self.at(|p| Ok(p.reader().read_exact(&mut block)?))?;
+        let inflated;
+        let (names, compressed) = match split_names(&block, count) {
+            Some(names) => (names, false),
+            None if is_zlib(&block) => {
+                // names are at most 255 bytes and their nul
+                let limit = count as u64 * 256;
+                let mut data = Vec::new();
+                flate2::read::ZlibDecoder::new(&block[..]).take(limit).read_to_end(&mut data)
+                    .map_err(|_| Error::TruncatedNameTable)?;
+                inflated = data;
+                (split_names(&inflated, count).ok_or(Error::TruncatedNameTable)?, true)
+            }
+            None => return Err(Error::TruncatedNameTable),
+        };
+        let names = names.into_iter().map(|name| {
+            let filename = CString::new(name).map_err(|_| Error::MalformedString)?;
+            trace!("{:?}", filename);
+            Ok(filename)
+        }).collect::<Result<_>>()?;
+        Ok((names, compressed))
+    }
+
+    /// Run a read, wrapping any error with the offset the read started at.
+    fn at<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let offset = self.reader().stream_position()?;
+        read(self).map_err(|e| Error::At { offset, source: Box::new(e) })
+    }
+
+    /// Read the archive header, checking the file id magic first.
+    fn read_header(&mut self) -> Result<BSAHeader> {
+        let mut magic = [0; 4];
+        self.reader().read_exact(&mut magic)?;
+        if &magic != b"BSA\0" {
+            return Err(Error::BadMagic(magic));
+        }
+        self.reader().seek(SeekFrom::Current(-4))?;
+        Ok(self.read()?)
+    }
+}
+
+impl<R: Read + Seek + ArchiveSource + 'static> BSAParser<R> {
+    /// Parse an archive of any supported version.
+    ///
+    /// The version field of the header selects the record layout.
+    pub fn parse(mut self) -> Result<BSAArchive> {
+        // morrowind archives have no file id, just the version
+        let mut magic = [0; 4];
+        self.reader().read_exact(&mut magic)?;
+        self.reader().seek(SeekFrom::Current(-4))?;
+        if magic == 0x100u32.to_le_bytes() {
+            return self.morrowind();
+        }
+
+        let header = self.at(Self::read_header)?;
+        let version = header.version;
+        match version {
+            103..=105 => self.tes4(header),
+            version => Err(Error::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Parse only the archive index, dropping the reader once the records and
+    /// names are read.
+    pub fn index_only(self) -> Result<BSAIndex> {
+        self.parse().map(BSAArchive::into_index)
+    }
+
+    /// Parser for version 103 of BSA used in Oblivion.
+    ///
+    /// Version 103 shares the header, folder record and file record layout of
+    /// version 104. The differences are in the archive flags: bit 0x100 has no
+    /// embedded file name meaning and compressed data is always zlib, so the
+    /// records are read by the same routine as [`BSAParser::v104`].
+    ///
+    /// Fails with [`Error::VersionMismatch`] on archives of other versions,
+    /// as do the other version specific parsers.
+    pub fn v103(mut self) -> Result<BSAArchive> {
+        let header = self.read_version(103)?;
+        self.tes4(header)
+    }
+
+    /// Parser for version 104 of BSA used in Fallout 3.
+    pub fn v104(mut self) -> Result<BSAArchive> {
+        let header = self.read_version(104)?;
+        self.tes4(header)
+    }
+
+    /// Parser for version 105 of BSA used in Skyrim Special Edition.
+    ///
+    /// Folder records carry a padding field and a 64-bit offset, compressed
+    /// file data uses LZ4 frames instead of zlib.
+    pub fn v105(mut self) -> Result<BSAArchive> {
+        let header = self.read_version(105)?;
+        self.tes4(header)
+    }
+
+    /// Read the folder records, folder names, file records and file names
+    /// shared by the TES4 era archive versions.
+    fn tes4(mut self, header: BSAHeader) -> Result<BSAArchive> {
+        debug!("{:?}", header);
+
+        // versions 103 to 105 share the 36 byte header, folder records follow
+        // it directly, a different size would shift every later read
+        let size = std::mem::size_of::<BSAHeader>() as u32;
+        let offset = header.offset;
+        if offset != size {
+            return Err(Error::HeaderSizeMismatch { expected: size, found: offset });
+        }
+
+        let mut folders = BSAHashMap::<BSAFolder>::with_kind(self.options.index);
+
+        let flags = ArchiveFlags::from_bits_retain(header.archive_flags);
+        for bits in [
+            header.archive_flags & !ArchiveFlags::all().bits(),
+            header.file_flags & !ContentFlags::all().bits(),
+        ] {
+            if bits == 0 { continue; }
+            if self.options.strict { return Err(Error::UnknownFlags { bits }); }
+            warn!("unknown header flag bits {:#x}", bits);
+        }
+
+        // archive wide compression, individual files may invert this
+        let compressed = flags.contains(ArchiveFlags::COMPRESSED);
+
+        // archive length, restoring the position after the header
+        let position = self.reader().stream_position()?;
+        let length = self.reader().seek(SeekFrom::End(0))?;
+        self.reader().seek(SeekFrom::Start(position))?;
+
+        // xbox archives store everything after the header big-endian
+        let big_endian = flags.contains(ArchiveFlags::BIG_ENDIAN);
+        let order32 = |v: u32| if big_endian { v.swap_bytes() } else { v };
+        let order64 = |v: u64| if big_endian { v.swap_bytes() } else { v };
+
+        let validate = self.options.validate;
+        let mut previous = None;
+        let record_size = if header.version >= 105 { 24 } else { 16 };
+        let remaining = length.saturating_sub(position);
+        let mut folder_order = Vec::with_capacity(capacity(header.folder_count as u64, record_size, remaining));
+        let mut folder_blocks = Vec::with_capacity(capacity(header.folder_count as u64, record_size, remaining));
+        let mut folder_collisions = Vec::new();
+        for _ in 0..header.folder_count {
+            let (hash, count, offset) = if header.version >= 105 {
+                let folder: BSAFolderRecordV105 = self.at(|p| Ok(p.read()?))?;
+                (order64(folder.name_hash), order32(folder.count), order64(folder.offset))
+            } else {
+                let folder: BSAFolderRecord = self.at(|p| Ok(p.read()?))?;
+                (order64(folder.name_hash), order32(folder.count), order32(folder.offset) as u64)
+            };
+            if validate { check_sorted(&mut previous, hash)?; }
+            check_bounds(offset, 0, length)?;
+            // folders sharing a hash are merged, keeping the files of both
+            match folders.get_hash_mut(hash) {
+                Some(folder) => {
+                    warn!("folder hash collision {:#018x}", hash);
+                    folder.count = folder.count.checked_add(count).ok_or(Error::IntegerOverflow)?;
+                    if !folder_collisions.contains(&hash) { folder_collisions.push(hash); }
+                }
+                None => {
+                    folders.insert(hash, BSAFolder::new(count, offset));
+                    folder_order.push(hash);
+                }
+            }
+            folder_blocks.push((hash, count, offset));
+            trace!("folder {:#018x} count {} offset {:#x}", hash, count, offset);
+        }
+
+        // the folder counts must account for every file the header declares
+        let declared = header.file_count;
+        let actual: u64 = folder_blocks.iter().map(|&(_, count, _)| count as u64).sum();
+        if actual != declared as u64 {
+            return Err(Error::FileCountMismatch { declared, actual });
+        }
+
+        if self.options.lazy {
+            return self.tes4_lazy(header, folders, folder_order, folder_collisions, folder_blocks, length);
+        }
+
+        // file records in record order, matching the order of the name table
+        let remaining = length.saturating_sub(self.reader().stream_position()?);
+        let mut records = Vec::with_capacity(capacity(header.file_count as u64, 16, remaining));
+
+        // bytes of folder names read, without their length prefix
+        let mut folder_names_length = 0;
+        for (_, count, _) in folder_blocks {
+            let start = self.reader().stream_position()?;
+            let name = self.at(Self::read_bzstring)?;
+            folder_names_length += self.reader().stream_position()? - start - 1;
+            let folder_hash = tes4_hash(&normalize_bytes(name.as_bytes()), b"");
+            let folder = folders.get_hash_mut(folder_hash).ok_or(Error::MissingFolder(folder_hash))?;
+            debug!("{:?} {:#018x}", name, folder_hash);
+            // merged folders keep the name of the first folder read
+            folder.name.get_or_insert(name);
+            let remaining = length.saturating_sub(self.reader().stream_position()?);
+            let mut folder_files = Vec::with_capacity(capacity(count as u64, 16, remaining));
+            self.push();
+            let mut previous = None;
+            for _ in 0..count {
+                let file: BSAFileRecord = self.at(|p| Ok(p.read()?))?;
+                let (hash, size, offset) = (order64(file.name_hash), order32(file.size), order32(file.offset));
+                if validate { check_sorted(&mut previous, hash)?; }
+                // bit 30 inverts the archive compression, the size is the low 30 bits
+                let compressed = compressed ^ ((size & 0x40000000) != 0);
+                let size = size & 0x3fffffff;
+                check_bounds(offset as u64, size as u64, length)?;
+                records.push((hash, BSAFile { size, offset, compressed, folder: folder_hash, name: None }));
+                folder_files.push(hash);
+                trace!("  {:?}", file);
+            }
+            self.pop();
+            if let Some(folder) = folders.get_hash_mut(folder_hash) {
+                folder.files.extend(folder_files);
+            }
+        }
+        let expected = header.total_folder_name_length as u64;
+        if flags.contains(ArchiveFlags::DIRECTORY_NAMES) && folder_names_length != expected {
+            return Err(Error::FolderNameCountMismatch { expected, found: folder_names_length });
+        }
+
+        // list of filenames delimited by nul byte, bounded by the name block size,
+        // an archive without file records has no names to read
+        let names_offset = self.reader().stream_position()?;
+        let block_size = if flags.contains(ArchiveFlags::FILE_NAMES) { header.total_file_name_length } else { 0 };
+        let mut hash_mismatches = Vec::new();
+        let mut names_compressed = false;
+        let skip_names = self.options.skip_names;
+        if flags.contains(ArchiveFlags::FILE_NAMES) && !records.is_empty() && !skip_names {
+            let (names, compressed) = self.read_file_names(block_size, records.len(), length)?;
+            names_compressed = compressed;
+            for ((hash, file), name) in records.iter_mut().zip(names) {
+                check_name_hash(*hash, &name, &mut hash_mismatches);
+                file.name = Some(name);
+            }
+        }
+
+        let (files, collisions) = index_files(records, self.options.index);
+
+        // now comes files, hand the reader over to the archive for extraction
+        let data_base = names_offset + block_size as u64;
+        let index = BSAIndex {
+            header, folders, files, order: folder_order, collisions, folder_collisions, hash_mismatches,
+            data_base, length, lazy: LazyFolders::default(), names_skipped: skip_names, names_compressed,
+        };
+        Ok(BSAArchive { index, source: Box::new(self.reader) })
+    }
+
+    /// Finish a lazy parse after the folder records, reading only the file
+    /// name table and recording where each folder block starts.
+    ///
+    /// The name table follows the folder blocks, its offset is the sum of the
+    /// header, folder record, folder name and file record sizes.
+    fn tes4_lazy(
+        mut self,
+        header: BSAHeader,
+        folders: BSAHashMap<BSAFolder>,
+        order: Vec<u64>,
+        folder_collisions: Vec<u64>,
+        folder_blocks: Vec<(u64, u32, u64)>,
+        length: u64,
+    ) -> Result<BSAArchive> {
+        let flags = ArchiveFlags::from_bits_retain(header.archive_flags);
+        let record_size: u64 = if header.version >= 105 { 24 } else { 16 };
+        let names_offset = header.offset as u64
+            + (record_size + 1) * header.folder_count as u64
+            + header.total_folder_name_length as u64
+            + 16 * header.file_count as u64;
+        let block_size = if flags.contains(ArchiveFlags::FILE_NAMES) { header.total_file_name_length } else { 0 };
+
+        let mut lazy = LazyFolders::default();
+        let mut names_compressed = false;
+        if flags.contains(ArchiveFlags::FILE_NAMES) && header.file_count != 0 && !self.options.skip_names {
+            self.reader().seek(SeekFrom::Start(names_offset))?;
+            (lazy.names, names_compressed) = self.read_file_names(block_size, to_usize(header.file_count)?, length)?;
+        }
+
+        // folder record offsets count the file name table as well
+        let mut first = 0;
+        for (hash, count, offset) in folder_blocks {
+            let offset = offset.checked_sub(header.total_file_name_length as u64).ok_or(Error::IntegerOverflow)?;
+            lazy.blocks.entry(hash).or_default().push((offset, count, first));
+            first += to_usize(count)?;
+        }
+
+        let index = BSAIndex {
+            header, folders, files: BSAHashMap::with_kind(self.options.index), order,
+            collisions: Vec::new(), folder_collisions, hash_mismatches: Vec::new(),
+            data_base: names_offset + block_size as u64, length, lazy, names_skipped: self.options.skip_names,
+            names_compressed,
+        };
+        Ok(BSAArchive { index, source: Box::new(self.reader) })
+    }
+}
+
+//------------------------------------------------------------------------------
+
+pub mod prelude {
+    pub use chunk_parser::prelude::*;
+    pub use super::BSAParser;
+    pub use super::set::BSAArchiveSet;
+    pub use super::writer::BSAWriter;
+}
+
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::prelude::*;
+
+    #[test]
+    fn tes4_hash_vectors() {
+        use super::tes4_hash;
+        // folder paths, no extension
+        assert_eq!(tes4_hash(b"meshes", b""), 0x322f3a9a6d066573);
+        assert_eq!(tes4_hash(b"textures", b""), 0xd507789e74086573);
+        assert_eq!(tes4_hash(b"meshes\\armor", b""), 0xb6e5bfe86d0c6f72);
+        assert_eq!(tes4_hash(b"textures\\clutter", b""), 0x048bf47c74106572);
+        // names shorter than 4 chars
+        assert_eq!(tes4_hash(b"a", b""), 0x0000000061010061);
+        assert_eq!(tes4_hash(b"ab", b""), 0x0000000061020062);
+        assert_eq!(tes4_hash(b"abc", b""), 0x0000000061036263);
+        // file names with special and plain extensions
+        assert_eq!(tes4_hash(b"sky", b".nif"), 0x92cd45fd7303eb79);
+        assert_eq!(tes4_hash(b"menu", b".dds"), 0x8ddbaa2a6d04eef5);
+        assert_eq!(tes4_hash(b"abc", b".dds"), 0x8ddba9c56103e2e3);
+        assert_eq!(tes4_hash(b"readme", b".txt"), 0xc7eddcea72066d65);
+    }
+
+    #[test]
+    fn tes4_hash_extension_folding() {
+        use super::tes4_hash;
+        assert_eq!(tes4_hash(b"idle", b".kf"), 0x1711e44d69046ce5);
+        assert_eq!(tes4_hash(b"h", b".kf"), 0x1711e3e9680100e8);
+        assert_eq!(tes4_hash(b"fx", b".wav"), 0x9733cf9ee6020078);
+        // name and extension string hashes overflow 32 bits when summed
+        assert_eq!(tes4_hash(b"skeleton", b".nif"), 0x875cfa7e7308ef6e);
+        assert_eq!(tes4_hash(b"glassarmorcuirass", b".dds"), 0x6ff0fc216711f3f3);
+    }
+
+    #[test]
+    fn tes4_hash_special_extensions() {
+        use super::hash_path;
+        // reference hashes from an independent implementation of the uesp algorithm,
+        // 3 char names have no middle string hash, 4 char names hash one char
+        for (path, hash) in [
+            ("pot.nif", 0x92cd45fd7003ef74),
+            ("pot.kf", 0x1711e3e970036ff4),
+            ("pot.dds", 0x8ddba9c57003eff4),
+            ("pot.wav", 0x9733cf9ef0036f74),
+            ("pot.tga", 0x95bfa28070036f74),
+            ("pots.nif", 0x92cd466c7004f473),
+            ("pots.kf", 0x1711e458700474f3),
+            ("pots.dds", 0x8ddbaa347004f4f3),
+            ("pots.wav", 0x9733d00df0047473),
+            ("pots.tga", 0x95bfa2ef70047473),
+        ] {
+            assert_eq!(hash_path(path), hash, "{}", path);
+        }
+    }
+
+    #[test]
+    fn hash_path_case_insensitive() {
+        use super::{hash_path, tes4_hash};
+        assert_eq!(hash_path("Menu.DDS"), tes4_hash(b"menu", b".dds"));
+        assert_eq!(hash_path("MESHES\\Armor"), tes4_hash(b"meshes\\armor", b""));
+    }
+
+    #[test]
+    fn hash_components() {
+        use super::{hash_file, hash_folder, tes4_hash};
+        assert_eq!(hash_folder("Meshes/Armor"), tes4_hash(b"meshes\\armor", b""));
+        assert_eq!(hash_folder("meshes\\v1.0"), tes4_hash(b"meshes\\v1.0", b""));
+        assert_eq!(hash_file("meshes/armor/Sky.nif"), tes4_hash(b"sky", b".nif"));
+    }
+
+    #[test]
+    fn hash_path_bytes() {
+        use crate::{hash_folder, hash_path, hash_path_bytes, tes4_hash};
+        assert_eq!(hash_path_bytes(b"meshes\\a.nif"), hash_path("Meshes/A.NIF"));
+        assert_eq!(hash_path_bytes(b"textures"), hash_folder("textures"));
+        // long and non-ASCII paths are normalized on the heap
+        let long = format!("{}\\b.dds", "a".repeat(300));
+        assert_eq!(hash_path(&long.to_uppercase()), hash_path_bytes(long.as_bytes()));
+        assert_eq!(hash_path("Caf\u{c9}\\B.txt"), hash_path_bytes(b"caf\xc9\\b.txt"));
+        assert_eq!(hash_folder("Caf\u{c9}"), tes4_hash(b"caf\xc9", b""));
+    }
+
+    #[test]
+    fn hash_folder_vectors() {
+        use super::hash_folder;
+        // folder record hashes as stored in the vanilla archives
+        assert_eq!(hash_folder("Meshes"), 0x322f3a9a6d066573);
+        assert_eq!(hash_folder("TEXTURES"), 0xd507789e74086573);
+        assert_eq!(hash_folder("meshes/armor"), 0xb6e5bfe86d0c6f72);
+        assert_eq!(hash_folder("Textures\\Clutter"), 0x048bf47c74106572);
+        assert_eq!(hash_folder(""), 0);
+    }
+
+    #[test]
+    fn hash_map_separators() {
+        use super::{tes4_hash, BSAHashMap};
+        let mut folders = BSAHashMap::default();
+        folders.insert(tes4_hash(b"meshes\\armor", b""), 1);
+        assert_eq!(folders.get("meshes\\armor"), Some(&1));
+        assert_eq!(folders.get("meshes/armor"), Some(&1));
+        assert_eq!(folders.get("meshes/armor/"), None);
+    }
+
+    #[test]
+    fn hash_map_sorted() {
+        use super::{tes4_hash, BSAHashMap, IndexKind};
+        let mut folders = BSAHashMap::with_kind(IndexKind::Sorted);
+        folders.insert(tes4_hash(b"textures", b""), 2);
+        folders.insert(tes4_hash(b"meshes", b""), 1);
+        assert_eq!(folders.get("meshes"), Some(&1));
+        assert_eq!(folders.get("textures"), Some(&2));
+        assert!(!folders.contains_key("sound"));
+        assert_eq!(folders.len(), 2);
+        let values: Vec<_> = folders.iter().map(|(_, v)| *v).collect();
+        assert_eq!(values, [1, 2]);
+    }
+
+    #[test]
+    fn hash_map_entry() {
+        use super::{BSAHashMap, Entry, IndexKind};
+        for kind in [IndexKind::Hash, IndexKind::Sorted] {
+            let mut files = BSAHashMap::with_kind(kind);
+            *files.entry("meshes/b.nif").or_insert(1) += 1;
+            files.entry("Meshes\\B.nif").and_modify(|v| *v *= 10).or_insert(0);
+            *files.entry("a.nif").or_default() += 5;
+            assert_eq!(files.get("meshes/b.nif"), Some(&20));
+            assert_eq!(files.get("a.nif"), Some(&5));
+            assert!(matches!(files.entry("c.nif"), Entry::Vacant(_)));
+            assert_eq!(files.len(), 2);
+        }
+    }
+
+    #[test]
+    fn sorted_records() {
+        use super::check_sorted;
+        let mut previous = None;
+        assert!(check_sorted(&mut previous, 1).is_ok());
+        assert!(check_sorted(&mut previous, 2).is_ok());
+        assert!(check_sorted(&mut previous, 2).is_err());
+        assert!(check_sorted(&mut previous, 1).is_err());
+    }
+
+    /// Build an in-memory v104 archive, shared with the tests of other modules.
+    pub(crate) fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        archive_with_filenames(files, true)
+    }
+
+    /// Build an in-memory v104 archive, with or without the file name table.
+    pub(crate) fn archive_with_filenames(files: &[(&str, &[u8])], filenames: bool) -> Vec<u8> {
+        let writer: BSAWriter = files.iter().map(|(path, data)| (path, data.to_vec())).collect();
+        let mut out = std::io::Cursor::new(Vec::new());
+        writer.with_filenames(filenames).write_to(&mut out).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn from_bytes() -> crate::Result<()> {
+        let data = archive(&[("meshes/a.nif", b"a"), ("textures/c.dds", b"ccc")]);
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        assert_eq!(bsa.file_count(), 2);
+        assert_eq!(bsa.folder_count(), 2);
+        assert_eq!(bsa.flags(), crate::ArchiveFlags::DIRECTORY_NAMES | crate::ArchiveFlags::FILE_NAMES);
+        assert_eq!(bsa.extract("meshes/a.nif")?, b"a");
+        assert_eq!(bsa.extract("textures\\c.dds")?, b"ccc");
+        assert_eq!(bsa.file_bytes("textures/c.dds"), Some(&b"ccc"[..]));
+        assert!(matches!(bsa.extract("textures/d.dds"), Err(crate::Error::FileNotFound(_))));
+        assert_eq!(bsa.extract_by_hash(crate::hash_file("a.nif"))?, b"a");
+        assert!(matches!(bsa.extract_by_hash(0), Err(crate::Error::FileNotFound(_))));
+        assert_eq!(bsa.stat("Meshes/A.nif").map(|info| info.size), Some(1));
+        assert_eq!(bsa.paths(), ["meshes\\a.nif", "textures\\c.dds"]);
+        assert_eq!(bsa.paths_with_prefix("Textures/"), ["textures\\c.dds"]);
+        assert!(bsa.paths_with_prefix("sound").is_empty());
+        let files = bsa.folder_files("Textures").unwrap();
+        assert_eq!(files.iter().map(|file| file.size).collect::<Vec<_>>(), [3]);
+        assert!(bsa.folder_files("sound").is_none());
+        assert_eq!(bsa.total_size(), 4);
+        assert_eq!(bsa.total_uncompressed_size()?, 4);
+        let debug = format!("{:?}", bsa);
+        assert!(debug.starts_with("BSAArchive { header: ") && debug.ends_with("folders: 2, files: 2, collisions: 0, .. }"));
+        Ok(())
+    }
+
+    #[test]
+    fn offset_out_of_bounds() {
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        // header, one folder record, "a" bzstring, then the file record offset
+        let offset = 36 + 16 + 3 + 12;
+        data[offset..offset + 4].copy_from_slice(&0xffff0000u32.to_le_bytes());
+        let result = BSAParser::from_bytes(data).parse();
+        assert!(matches!(result, Err(crate::Error::OffsetOutOfBounds { offset: 0xffff0000, size: 1 })));
+    }
+
+    #[test]
+    fn extract_verified() -> crate::Result<()> {
+        let bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"123456789")])).parse()?;
+        assert_eq!(bsa.extract_verified("a/b.txt", 0xcbf43926)?, b"123456789");
+        let result = bsa.extract_verified("a/b.txt", 0);
+        assert!(matches!(result, Err(crate::Error::ChecksumMismatch { expected: 0, actual: 0xcbf43926 })));
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_name_table() {
+        let mut data = archive(&[("a/b.txt", b"b"), ("a/c.txt", b"c")]);
+        // total file name length in the header, short by one name
+        data[28..32].copy_from_slice(&6u32.to_le_bytes());
+        let result = BSAParser::from_bytes(data).parse();
+        assert!(matches!(result, Err(crate::Error::TruncatedNameTable)));
+    }
+
+    #[test]
+    fn large_offset() -> crate::Result<()> {
+        use crate::ArchiveSource;
+
+        /// Source holding a block of data at a fixed offset.
+        struct Block(u64, Vec<u8>);
+        impl ArchiveSource for Block {
+            fn read_at(&self, offset: u64, len: usize) -> crate::Result<Vec<u8>> {
+                let offset = offset.checked_sub(self.0).ok_or(crate::Error::IntegerOverflow)?;
+                (&self.1[..]).read_at(offset, len)
+            }
+        }
+
+        let mut bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"b")])).parse()?;
+        bsa.header.archive_flags |= crate::ArchiveFlags::EMBEDDED_NAMES.bits();
+        // embedded name ends past 4GB, 32-bit offset math would wrap
+        let offset = u32::MAX - 4;
+        let mut block = vec![32];
+        block.extend([b'x'; 32]);
+        block.extend(b"data");
+        let file = bsa.files.get_mut("b.txt").unwrap();
+        (file.offset, file.size) = (offset, block.len() as u32);
+        bsa.source = Box::new(Block(offset as u64, block));
+        assert_eq!(bsa.extract("a/b.txt")?, b"data");
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn extract_all_parallel() -> crate::Result<()> {
+        let bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"b"), ("c/d.txt", b"dd"), ("e/d.txt", b"ed")])).parse()?;
+        let dst = std::env::temp_dir().join("bsa-parser-parallel");
+        let _ = std::fs::remove_dir_all(&dst);
+        assert_eq!(bsa.extract_all_parallel(&dst, Default::default())?, 3);
+        assert_eq!(std::fs::read(dst.join("a/b.txt"))?, b"b");
+        assert_eq!(std::fs::read(dst.join("c/d.txt"))?, b"dd");
+        assert_eq!(std::fs::read(dst.join("e/d.txt"))?, b"ed");
+        std::fs::remove_dir_all(&dst)?;
+        Ok(())
+    }
+
+    #[test]
+    fn index_only() -> crate::Result<()> {
+        let data = archive(&[("meshes/a.nif", b"a"), ("textures/c.dds", b"ccc")]);
+        let index = BSAParser::from_bytes(data).index_only()?;
+        assert_eq!(index.paths(), ["meshes\\a.nif", "textures\\c.dds"]);
+        assert_eq!(index.stat("textures/c.dds").map(|info| info.size), Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn hash_collision() -> crate::Result<()> {
+        let bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"ab"), ("c/b.txt", b"cb")])).parse()?;
+        assert_eq!(bsa.file_count(), 2);
+        assert_eq!(bsa.files().count(), 2);
+        assert_eq!(bsa.total_size(), 4);
+        assert_eq!(bsa.total_uncompressed_size()?, 4);
+        assert_eq!(bsa.collisions().count(), 1);
+        assert_eq!(bsa.extract("a/b.txt")?, b"ab");
+        assert_eq!(bsa.extract("c/b.txt")?, b"cb");
+        assert_eq!(bsa.paths(), ["a\\b.txt", "c\\b.txt"]);
+        assert!(matches!(bsa.extract("x/b.txt"), Err(crate::Error::FileNotFound(_))));
+        assert!(bsa.stat("b.txt").is_none());
+        let (_, file) = bsa.collisions().next().unwrap();
+        assert_eq!(bsa.extract_file(file)?, b"cb");
+
+        let dst = std::env::temp_dir().join("bsa-parser-collision");
+        let _ = std::fs::remove_dir_all(&dst);
+        assert_eq!(bsa.extract_all(&dst, Default::default())?, 2);
+        assert_eq!(std::fs::read(dst.join("c/b.txt"))?, b"cb");
+        std::fs::remove_dir_all(&dst)?;
+        Ok(())
+    }
+
+    #[test]
+    fn sorted_index() -> crate::Result<()> {
+        let data = archive(&[("a/b.txt", b"ab"), ("a/z.txt", b"az"), ("c/b.txt", b"cb")]);
+        let bsa = BSAParser::from_bytes(data).index(crate::IndexKind::Sorted).parse()?;
+        let hashes: Vec<u64> = bsa.files.iter().map(|(hash, _)| hash).collect();
+        assert!(hashes.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!((bsa.files.len(), bsa.collisions().count()), (2, 1));
+        assert_eq!(bsa.extract("a/b.txt")?, b"ab");
+        assert_eq!(bsa.extract("c/b.txt")?, b"cb");
+        assert_eq!(bsa.extract("a/z.txt")?, b"az");
+        Ok(())
+    }
+
+    #[test]
+    fn folder_hash_collision() -> crate::Result<()> {
+        use crate::hash_folder;
+        // the middles "aevujadk" and "aafajuto" share a string hash
+        let hash = hash_folder("xaevujadkyz");
+        assert_eq!(hash_folder("xaafajutoyz"), hash);
+        let mut data = archive(&[("xaevujadkyz/a.txt", b"a"), ("xbbbbbbbbyz/b.txt", b"b")]);
+        let replace = |data: &mut Vec<u8>, from: &[u8], to: &[u8]| {
+            let i = data.windows(from.len()).position(|window| window == from).unwrap();
+            data[i..i + from.len()].copy_from_slice(to);
+        };
+        replace(&mut data, &hash_folder("xbbbbbbbbyz").to_le_bytes(), &hash.to_le_bytes());
+        replace(&mut data, b"xbbbbbbbbyz", b"xaafajutoyz");
+
+        let bsa = BSAParser::from_bytes(data.clone()).parse()?;
+        assert_eq!(bsa.folder_collisions(), [hash]);
+        assert_eq!((bsa.folder_count(), bsa.file_count()), (1, 2));
+        let folder = bsa.folders.get_hash(hash).and_then(|folder| folder.name.clone());
+        assert_eq!(folder.as_deref(), Some(c"xaevujadkyz"));
+        assert_eq!(bsa.extract("xaevujadkyz/a.txt")?, b"a");
+        assert_eq!(bsa.extract("xaafajutoyz/b.txt")?, b"b");
+
+        // merged counts overflowing u32
+        data[44..48].copy_from_slice(&u32::MAX.to_le_bytes());
+        data[60..64].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(BSAParser::from_bytes(data).parse(), Err(crate::Error::IntegerOverflow)));
+        Ok(())
+    }
+
+    #[test]
+    fn lazy() -> crate::Result<()> {
+        let data = archive(&[("meshes/a.nif", b"a"), ("meshes/b.nif", b"bb"), ("textures/c.dds", b"ccc")]);
+        let data_base = BSAParser::from_bytes(data.clone()).parse()?.file_data_base();
+        let mut bsa = BSAParser::from_bytes(data).lazy(true).parse()?;
+        assert_eq!((bsa.folder_count(), bsa.file_count()), (2, 0));
+        assert_eq!(bsa.file_data_base(), data_base);
+        assert!(matches!(bsa.extract("textures/c.dds"), Err(crate::Error::FolderNotLoaded(_))));
+        assert!(matches!(bsa.extract("sound/c.wav"), Err(crate::Error::FileNotFound(_))));
+
+        let files = bsa.load_folder("Textures")?.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name.as_ref().map(|name| name.as_bytes()), Some(&b"c.dds"[..]));
+        assert_eq!(bsa.file_count(), 1);
+        assert_eq!(bsa.extract("textures/c.dds")?, b"ccc");
+        assert_eq!(bsa.load_folder("textures")?.unwrap().len(), 1);
+
+        assert_eq!(bsa.load_folder("meshes")?.unwrap().len(), 2);
+        assert_eq!(bsa.paths(), ["meshes\\a.nif", "meshes\\b.nif", "textures\\c.dds"]);
+        assert!(bsa.load_folder("sound")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn version_mismatch() -> crate::Result<()> {
+        use crate::Error;
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        assert!(BSAParser::from_bytes(data.clone()).v104().is_ok());
+        let result = BSAParser::from_bytes(data.clone()).v103();
+        assert!(matches!(result, Err(Error::VersionMismatch { expected: 103, found: 104 })));
+        let result = BSAParser::from_bytes(data.clone()).v105();
+        assert!(matches!(result, Err(Error::VersionMismatch { expected: 105, found: 104 })));
+        data[4] = 105;
+        let result = BSAParser::from_bytes(data).v104();
+        assert!(matches!(result, Err(Error::VersionMismatch { expected: 104, found: 105 })));
+        Ok(())
+    }
+
+    #[test]
+    fn has_names() -> crate::Result<()> {
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        assert!(BSAParser::from_bytes(data.clone()).parse()?.has_names());
+        data[12] = 0x1; // drop the file names flag
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        assert!(!bsa.has_names());
+        assert_eq!(bsa.extract("a/b.txt")?, b"b");
+        let dst = std::env::temp_dir().join("bsa-parser-has-names");
+        assert!(matches!(bsa.extract_all(&dst, Default::default()), Err(crate::Error::MissingNames)));
+        Ok(())
+    }
+
+    #[test]
+    fn hash_mismatches() -> crate::Result<()> {
+        use crate::hash_path;
+        let data = archive(&[("a/b.txt", b"b")]);
+        assert!(BSAParser::from_bytes(data.clone()).parse()?.hash_mismatches().is_empty());
+        let mut data = data;
+        let i = data.windows(6).position(|name| name == b"b.txt\0").unwrap();
+        data[i] = b'c';
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        assert_eq!(bsa.hash_mismatches(), [(hash_path("b.txt"), hash_path("c.txt"))]);
+        Ok(())
+    }
+
+    #[test]
+    fn extract_into_with() -> crate::Result<()> {
+        let bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"bbbb"), ("c/d.txt", b"d")])).parse()?;
+        let (mut out, mut scratch) = (Vec::new(), Vec::new());
+        assert_eq!(bsa.extract_into_with("a/b.txt", &mut out, &mut scratch)?, 4);
+        out.clear();
+        assert_eq!(bsa.extract_into_with("c/d.txt", &mut out, &mut scratch)?, 1);
+        assert_eq!(out, b"d");
+        assert!(scratch.capacity() >= 4);
+        Ok(())
+    }
+
+    #[test]
+    fn header_size() -> crate::Result<()> {
+        use crate::Error;
+        assert_eq!(std::mem::size_of::<esm_bindings::bsa::BSAHeader>(), 36);
+        for version in [103, 104, 105] {
+            let mut data = archive(&[]);
+            data[4] = version;
+            assert!(BSAParser::from_bytes(data.clone()).parse().is_ok(), "{}", version);
+            data[8] = 40;
+            let result = BSAParser::from_bytes(data).parse();
+            assert!(matches!(result, Err(Error::HeaderSizeMismatch { expected: 36, found: 40 })), "{}", version);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn drain_files() -> crate::Result<()> {
+        use crate::hash_path;
+        let bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"ab"), ("c/b.txt", b"cb"), ("d.txt", b"dd")])).parse()?;
+        let files: Vec<_> = bsa.drain_files().collect::<crate::Result<_>>()?;
+        let mut expected = vec![
+            (hash_path("b.txt"), "a/b.txt", b"ab"),
+            (hash_path("b.txt"), "c/b.txt", b"cb"),
+            (hash_path("d.txt"), "d.txt", b"dd"),
+        ];
+        expected.sort_by_key(|&(hash, _, _)| hash);
+        let expected: Vec<_> = expected.into_iter()
+            .map(|(_, path, data)| (path.to_string(), data.to_vec()))
+            .collect();
+        assert_eq!(files, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn record_constructors() -> crate::Result<()> {
+        use crate::{hash_folder, BSAFile, BSAFolder};
+        use std::ffi::CString;
+        let bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"ab")])).parse()?;
+        let (_, folder) = bsa.folders().next().unwrap();
+        let files = bsa.files().map(|(hash, _)| hash).collect();
+        let expected = BSAFolder { name: Some(CString::new("a").unwrap()), files, ..BSAFolder::new(1, folder.offset) };
+        assert_eq!(folder.clone(), expected);
+        let (_, file) = bsa.files().next().unwrap();
+        let name = Some(CString::new("b.txt").unwrap());
+        let expected = BSAFile { folder: hash_folder("a"), name, ..BSAFile::new(2, file.offset) };
+        assert_eq!(*file, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn folder_name_count_mismatch() {
+        let mut data = archive(&[("meshes/a.nif", b"a"), ("textures/c.dds", b"ccc")]);
+        data[24] += 1; // total folder name length, 16 bytes read
+        let result = BSAParser::from_bytes(data).parse();
+        assert!(matches!(result, Err(crate::Error::FolderNameCountMismatch { expected: 17, found: 16 })));
+    }
+
+    #[test]
+    fn position() -> crate::Result<()> {
+        let mut parser = BSAParser::from_bytes(archive(&[]));
+        assert_eq!(parser.position()?, 0);
+        parser.read_header()?;
+        assert_eq!(parser.position()?, 36);
+        Ok(())
+    }
+
+    #[test]
+    fn skip_names() -> crate::Result<()> {
+        let data = archive(&[("a/b.txt", b"b"), ("c/d.txt", b"dd")]);
+        let bsa = BSAParser::from_bytes(data.clone()).skip_names(true).parse()?;
+        assert!(!bsa.has_names() && bsa.names_skipped());
+        assert!(bsa.files().all(|(_, file)| file.name.is_none()));
+        assert_eq!(bsa.extract("c/d.txt")?, b"dd");
+        assert_eq!(bsa.file_data_base(), BSAParser::from_bytes(data).parse()?.file_data_base());
+        Ok(())
+    }
+
+    #[test]
+    fn extract_options() -> crate::Result<()> {
+        use crate::ExtractOptions;
+        let bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"b"), ("c/d.txt", b"dd")])).parse()?;
+        let dst = std::env::temp_dir().join("bsa-parser-extract-options");
+        let _ = std::fs::remove_dir_all(&dst);
+        std::fs::create_dir_all(dst.join("a"))?;
+        std::fs::write(dst.join("a/b.txt"), b"old")?;
+
+        let skip = ExtractOptions { skip_existing: true, ..Default::default() };
+        assert_eq!(bsa.extract_all(&dst, skip)?, 1);
+        assert_eq!(std::fs::read(dst.join("a/b.txt"))?, b"old");
+        assert_eq!(std::fs::read(dst.join("c/d.txt"))?, b"dd");
+        let result = bsa.extract_all(&dst, ExtractOptions::default());
+        assert!(matches!(result, Err(crate::Error::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists));
+        let overwrite = ExtractOptions { overwrite: true, ..Default::default() };
+        assert_eq!(bsa.extract_all(&dst, overwrite)?, 2);
+        assert_eq!(std::fs::read(dst.join("a/b.txt"))?, b"b");
+        std::fs::remove_dir_all(&dst)?;
+        Ok(())
+    }
+
+    #[test]
+    fn unsafe_path() -> crate::Result<()> {
+        use crate::safe_join;
+        let bsa = BSAParser::from_bytes(archive(&[("..\\..\\evil", b"evil")])).parse()?;
+        assert_eq!(bsa.paths(), ["..\\..\\evil"]);
+        let dst = std::env::temp_dir().join("bsa-parser-unsafe").join("out");
+        let result = bsa.extract_all(&dst, Default::default());
+        assert!(matches!(result, Err(crate::Error::UnsafePath(path)) if path == "../../evil"));
+        assert!(!dst.join("../../evil").exists());
+
+        let root = std::path::Path::new("out");
+        assert_eq!(safe_join(root, "/a/./b.txt")?, root.join("a").join("b.txt"));
+        assert!(safe_join(root, "c:\\evil").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn file_count_mismatch() -> crate::Result<()> {
+        let data = archive(&[("a/b.txt", b"ab"), ("c/b.txt", b"cb")]);
+        let bsa = BSAParser::from_bytes(data.clone()).parse()?;
+        assert_eq!((bsa.declared_file_count(), bsa.actual_file_count()), (2, 2));
+        assert_eq!(bsa.file_count(), 2);
+        let mut data = data;
+        data[20] = 3; // header file count
+        let result = BSAParser::from_bytes(data).parse();
+        assert!(matches!(result, Err(crate::Error::FileCountMismatch { declared: 3, actual: 2 })));
+        Ok(())
+    }
+
+    #[test]
+    fn trailing_data() -> crate::Result<()> {
+        let mut data = archive(&[("a/b.txt", b"b"), ("c/d.txt", b"dd")]);
+        let mut bsa = BSAParser::from_bytes(data.clone()).parse()?;
+        assert_eq!(bsa.file_data_end(), data.len() as u64);
+        assert!(bsa.trailing_data()?.is_empty());
+
+        data.extend(b"meta");
+        let mut bsa = BSAParser::from_bytes(data.clone()).parse()?;
+        assert_eq!(bsa.trailing_data()?, b"meta");
+        let mut bsa = BSAParser::from_bytes(data).lazy(true).parse()?;
+        assert_eq!(bsa.trailing_data()?, b"meta");
+        assert_eq!(bsa.file_count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn forged_counts() {
+        let data = archive(&[("a/b.txt", b"b")]);
+        // folder count
+        let mut header = data[..36].to_vec();
+        header[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        // file count, matched by the folder record count
+        let mut records = data.clone();
+        records[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        records[44..48].copy_from_slice(&u32::MAX.to_le_bytes());
+        for data in [header, records] {
+            for lazy in [false, true] {
+                let result = BSAParser::from_bytes(data.clone()).lazy(lazy).parse();
+                assert!(result.is_err());
+            }
+        }
+    }
+
+    /// Replace the name table of a single file archive with a zlib stream of
+    /// `table` in a single stored deflate block.
+    fn compress_names(data: &mut Vec<u8>, table: &[u8]) {
+        let i = data.windows(6).position(|name| name == b"b.txt\0").unwrap();
+        let adler = {
+            let (a, b) = table.iter().fold((1u32, 0u32), |(a, b), &byte| {
+                let a = (a + byte as u32) % 65521;
+                (a, (b + a) % 65521)
+            });
+            b << 16 | a
+        };
+        let len = table.len() as u16;
+        let mut zlib = vec![0x78, 0x01, 0x01];
+        zlib.extend(len.to_le_bytes());
+        zlib.extend((!len).to_le_bytes());
+        zlib.extend(table);
+        zlib.extend(adler.to_be_bytes());
+        let grown = (zlib.len() - 6) as u32;
+        data.splice(i..i + 6, zlib.iter().copied());
+        data[28..32].copy_from_slice(&(zlib.len() as u32).to_le_bytes()); // total file name length
+        // file data offset, the only file record ends the index
+        let offset = u32::from_le_bytes(data[i - 4..i].try_into().unwrap()) + grown;
+        data[i - 4..i].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    #[test]
+    fn compressed_names() -> crate::Result<()> {
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        compress_names(&mut data, b"b.txt\0");
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        assert!(bsa.names_compressed());
+        assert_eq!(bsa.paths(), ["a\\b.txt"]);
+        assert_eq!(bsa.extract("a/b.txt")?, b"b");
+
+        // inflating stops at the longest table the file count allows
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        let mut table = vec![b'b'; 300];
+        table.push(0);
+        compress_names(&mut data, &table);
+        let result = BSAParser::from_bytes(data).parse();
+        assert!(matches!(result, Err(crate::Error::TruncatedNameTable)));
+        Ok(())
+    }
+
+    #[test]
+    fn content_types() -> crate::Result<()> {
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        data[32] = 0x03;
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        assert_eq!(bsa.content_types(), crate::ContentFlags::MESHES | crate::ContentFlags::TEXTURES);
+        Ok(())
+    }
+
+    #[test]
+    fn windows_1252_names() -> crate::Result<()> {
+        let data = archive(&[("textures/Caf\u{e9}/\u{c0}.dds", b"a")]);
+        assert!(data.windows(6).any(|name| name == b"\xc0.dds\0"));
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        assert_eq!(bsa.paths(), ["textures\\caf\u{e9}\\\u{c0}.dds"]);
+        assert_eq!(bsa.extract("Textures/Caf\u{e9}/\u{c0}.dds")?, b"a");
+        Ok(())
+    }
+
+    #[test]
+    fn empty() -> crate::Result<()> {
+        // written by the writer, folder and file names flags set
+        let bsa = BSAParser::from_bytes(archive(&[])).parse()?;
+        assert_eq!((bsa.folder_count(), bsa.file_count()), (0, 0));
+        assert!(bsa.paths().is_empty());
+        assert_eq!(bsa.total_uncompressed_size()?, 0);
+
+        // bare header without flags, followed by stray bytes
+        let mut data = b"BSA\0".to_vec();
+        for value in [104u32, 36, 0, 0, 0, 0, 0, 0] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(b"stray");
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        assert_eq!((bsa.folder_count(), bsa.file_count()), (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn extract_all_to_map() -> crate::Result<()> {
+        let bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"b"), ("c/d.txt", b"dd")])).parse()?;
+        let files = bsa.extract_all_to_map()?;
+        assert_eq!(files.len(), 2);
+        assert_eq!(files["a\\b.txt"], b"b");
+        assert_eq!(files["c\\d.txt"], b"dd");
+        Ok(())
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn export_zip() -> crate::Result<()> {
+        let bsa = BSAParser::from_bytes(archive(&[("a/b.txt", b"b"), ("c/d.txt", b"dd")])).parse()?;
+        let mut out = std::io::Cursor::new(Vec::new());
+        bsa.export_zip(&mut out)?;
+        let mut zip = zip::ZipArchive::new(out).map_err(std::io::Error::other)?;
+        assert_eq!(zip.len(), 2);
+        let mut data = Vec::new();
+        std::io::Read::read_to_end(&mut zip.by_name("c/d.txt").map_err(std::io::Error::other)?, &mut data)?;
+        assert_eq!(data, b"dd");
+        Ok(())
+    }
+
+    #[test]
+    fn game() -> crate::Result<()> {
+        use crate::Game;
+        let bsa = BSAParser::from_bytes(archive(&[("meshes/a.nif", b"a")])).parse()?;
+        assert_eq!(bsa.game(), Game::Unknown);
+        let bsa = BSAParser::from_bytes(archive(&[("scripts/a.pex", b"a")])).parse()?;
+        assert_eq!(bsa.game(), Game::SkyrimLE);
+        let bsa = BSAParser::from_bytes(archive(&[("meshes/idle.kf", b"a")])).parse()?;
+        assert_eq!(bsa.game(), Game::Fallout3);
+        Ok(())
+    }
+
+    #[test]
+    fn xmem_compression() -> crate::Result<()> {
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        data[12] |= 0x4;
+        data[13] |= 0x2;
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        assert!(matches!(bsa.extract("a/b.txt"), Err(crate::Error::UnsupportedCompression)));
+        Ok(())
+    }
+
+    #[test]
+    fn strict() -> crate::Result<()> {
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        data[14] |= 0x10;
+        assert!(BSAParser::from_bytes(data.clone()).parse().is_ok());
+        let result = BSAParser::from_bytes(data).strict(true).parse();
+        assert!(matches!(result, Err(crate::Error::UnknownFlags { bits: 0x100000 })));
+        Ok(())
+    }
+
+    #[test]
+    fn error_offset() {
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        // truncated inside the folder record following the 36 byte header
+        data.truncate(40);
+        let result = BSAParser::from_bytes(data).parse();
+        assert!(matches!(result, Err(crate::Error::At { offset: 36, .. })));
+    }
+
+    #[test]
+    fn big_endian() -> crate::Result<()> {
+        use crate::{hash_file, hash_folder};
+        use std::io::Write;
+        let payload = b"stored big-endian";
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(payload).unwrap();
+        // compressed data starts with the uncompressed size
+        let mut stored = (payload.len() as u32).to_be_bytes().to_vec();
+        stored.extend(encoder.finish().unwrap());
+        let mut data = archive(&[("a/b.txt", b"b"), ("a/c.txt", &stored), ("d/e.txt", b"ee")]);
+
+        // swap every field after the header, marking c.txt compressed
+        let u32_at = |data: &[u8], at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        let swap = |data: &mut [u8], at: usize| {
+            data[at..at + 8].reverse(); // hash
+            data[at + 8..at + 12].reverse(); // count or size
+            data[at + 12..at + 16].reverse(); // offset
+        };
+        data[12] |= 0x40;
+        let folder_count = u32_at(&data, 16) as usize;
+        let mut at = 36 + 16 * folder_count;
+        for folder in 0..folder_count {
+            let record = 36 + 16 * folder;
+            let count = u32_at(&data, record + 8);
+            swap(&mut data, record);
+            at += 1 + data[at] as usize; // folder name
+            for _ in 0..count {
+                if data[at..at + 8] == hash_file("c.txt").to_le_bytes() {
+                    data[at + 11] |= 0x40;
+                }
+                swap(&mut data, at);
+                at += 16;
+            }
+        }
+
+        for lazy in [false, true] {
+            let mut bsa = BSAParser::from_bytes(data.clone()).lazy(lazy).parse()?;
+            assert!(bsa.big_endian());
+            for folder in ["a", "d"] {
+                bsa.load_folder(folder)?;
+            }
+            assert_eq!(bsa.folders.get_hash(hash_folder("a")).map(|folder| folder.count), Some(2));
+            assert!(bsa.files.contains_key("c.txt"));
+            assert!(bsa.hash_mismatches().is_empty());
+            assert_eq!(bsa.stat("a/c.txt").map(|info| info.compressed), Some(true));
+            assert_eq!(bsa.extract("a/b.txt")?, b"b");
+            assert_eq!(bsa.extract("a/c.txt")?, payload);
+            assert_eq!(bsa.extract("d/e.txt")?, b"ee");
+            assert_eq!(bsa.total_uncompressed_size()?, 1 + payload.len() as u64 + 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compression_override() -> crate::Result<()> {
+        // header, one folder record, "a" bzstring, then the high byte of the file record size
+        let size = 36 + 16 + 3 + 11;
+
+        // uncompressed archive, file marked compressed
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        data[size] |= 0x40;
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        let info = bsa.stat("a/b.txt").unwrap();
+        assert_eq!((info.size, info.compressed), (1, true));
+        assert_eq!(bsa.extract_raw("a/b.txt")?, (b"b".to_vec(), true));
+        // header, folder record, "a" bzstring, file record and "b.txt" name
+        assert_eq!(bsa.file_data_base(), 36 + 16 + 3 + 16 + 6);
+        assert_eq!(bsa.file_data_base(), info.offset as u64);
+
+        // compressed archive, file marked uncompressed
+        let mut data = archive(&[("a/b.txt", b"b")]);
+        data[12] |= 0x4;
+        data[size] |= 0x40;
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        let info = bsa.stat("a/b.txt").unwrap();
+        assert_eq!((info.size, info.compressed), (1, false));
+        assert_eq!(bsa.extract("a/b.txt")?, b"b");
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap() -> crate::Result<()> {
+        let path = std::env::temp_dir().join("bsa-parser-mmap.bsa");
+        std::fs::write(&path, archive(&[("a/b.txt", b"b"), ("c/d.txt", b"dd")]))?;
+        let bsa = BSAParser::mmap(&path)?.parse()?;
+        assert_eq!(bsa.extract("c/d.txt")?, b"dd");
+        assert_eq!(bsa.extract("a/b.txt")?, b"b");
+        assert_eq!(bsa.file_bytes("c/d.txt"), Some(&b"dd"[..]));
+        assert_eq!(bsa.file_bytes("c/e.txt"), None);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removed_file() -> crate::Result<()> {
+        // the parser hands its open file to the archive, the path is not reopened
+        let path = std::env::temp_dir().join("bsa-parser-removed.bsa");
+        std::fs::write(&path, archive(&[("a/b.txt", b"b")]))?;
+        let bsa = crate::BSAArchive::open(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(bsa.extract("a/b.txt")?, b"b");
+        Ok(())
+    }
+
+    /// Repack every named file of an archive with [`BSAWriter`].
+    fn repack(archive: &crate::BSAArchive) -> crate::Result<Vec<u8>> {
+        let mut writer = BSAWriter::new();
+        for path in archive.paths() {
+            let data = archive.extract(&path)?;
+            writer.add(&path, data);
+        }
+        let mut out = std::io::Cursor::new(Vec::new());
+        writer.write_to(&mut out)?;
+        Ok(out.into_inner())
+    }
+
+    #[test]
+    fn round_trip_byte_stable() -> crate::Result<()> {
+        let data = archive(&[("meshes/a.nif", b"a"), ("meshes/b.nif", b"bb"), ("textures/c.dds", b"ccc")]);
+        let bsa = BSAParser::from_bytes(data.clone()).parse()?;
+        assert_eq!(repack(&bsa)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> crate::Result<()> {
+        let original = BSAParser::file("data/Misc.bsa")?.parse()?;
+        let copy = BSAParser::from_bytes(repack(&original)?).parse()?;
+
+        fn folders(bsa: &crate::BSAArchive) -> Vec<(u64, u32)> {
+            let mut folders: Vec<_> = bsa.folders().map(|(hash, folder)| (hash, folder.count)).collect();
+            folders.sort();
+            folders
+        }
+        assert_eq!(folders(&original), folders(&copy));
+
+        assert_eq!(original.file_count(), copy.file_count());
+        assert_eq!(original.total_uncompressed_size()?, copy.total_size());
+        for path in original.paths() {
+            assert_eq!(original.extract(&path)?, copy.extract(&path)?, "{}", path);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn open() -> crate::Result<()> {
+        let bsa = crate::BSAArchive::open("data/Misc.bsa")?;
+        let copy = crate::BSAArchive::try_from(std::path::Path::new("data/Misc.bsa"))?;
+        assert_eq!(bsa.file_count(), copy.file_count());
+        Ok(())
+    }
 
     #[test]
-    fn misc() -> chunk_parser::Result<()> {
-        let mut bsa = BSAParser::file("data/Misc.bsa")?;
+    fn misc() -> crate::Result<()> {
+        let bsa = BSAParser::file("data/Misc.bsa")?;
         bsa.v104()?;
         Ok(())
     }
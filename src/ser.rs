@@ -0,0 +1,59 @@
+//! Serde serialization of archive metadata, enabled by the `serde` feature.
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use std::ffi::CString;
+
+use crate::strings::decode_name;
+use crate::{BSAArchive, BSAIndex, HeaderSummary};
+
+/// Serialize an optional name as a string, decoded with [`decode_name`].
+pub(crate) fn cstring<S: Serializer>(name: &Option<CString>, serializer: S) -> Result<S::Ok, S::Error> {
+    match name {
+        Some(name) => serializer.serialize_some(&decode_name(name.as_bytes())),
+        None => serializer.serialize_none(),
+    }
+}
+
+impl Serialize for HeaderSummary<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let header = self.0;
+        let mut state = serializer.serialize_struct("BSAHeader", 7)?;
+        state.serialize_field("version", &{ header.version })?;
+        state.serialize_field("archive_flags", &{ header.archive_flags })?;
+        state.serialize_field("folder_count", &{ header.folder_count })?;
+        state.serialize_field("file_count", &{ header.file_count })?;
+        state.serialize_field("total_folder_name_length", &{ header.total_folder_name_length })?;
+        state.serialize_field("total_file_name_length", &{ header.total_file_name_length })?;
+        state.serialize_field("file_flags", &{ header.file_flags })?;
+        state.end()
+    }
+}
+
+/// Archive entry paired with its hash.
+#[derive(serde::Serialize)]
+struct Entry<'a, T> {
+    hash: u64,
+    #[serde(flatten)]
+    entry: &'a T,
+}
+
+/// Serializes the header and entries.
+impl Serialize for BSAIndex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let folders: Vec<_> = self.folders.iter().map(|(hash, entry)| Entry { hash, entry }).collect();
+        let files: Vec<_> = self.files().map(|(hash, entry)| Entry { hash, entry }).collect();
+        let mut state = serializer.serialize_struct("BSAArchive", 3)?;
+        state.serialize_field("header", &HeaderSummary(&self.header))?;
+        state.serialize_field("folders", &folders)?;
+        state.serialize_field("files", &files)?;
+        state.end()
+    }
+}
+
+/// Serializes the index, the source is skipped.
+impl Serialize for BSAArchive {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
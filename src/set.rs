@@ -0,0 +1,135 @@
+//! Sets of archives read as one, like the archives a game loads in order.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::{hash_folder, hash_path, normalize_path, BSAArchive, BSAFile, Error, Result};
+
+/// Which member of a set provides a path found in several members.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Precedence {
+    /// Later members override earlier ones, as in the game load order.
+    #[default]
+    LastWins,
+    /// Earlier members are kept over later ones.
+    FirstWins,
+}
+
+/// Archives looked up as one logical archive, such as an archive split over
+/// several files.
+///
+/// Members are kept in the order they are added, paths resolve to the member
+/// picked by the [`Precedence`] among those containing the path.
+#[derive(Default)]
+pub struct BSAArchiveSet {
+    archives: Vec<BSAArchive>,
+    precedence: Precedence,
+}
+
+impl BSAArchiveSet {
+    pub fn new(precedence: Precedence) -> Self {
+        Self { archives: Vec::new(), precedence }
+    }
+
+    /// Open and parse each archive file, in order.
+    pub fn open<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>, precedence: Precedence) -> Result<Self> {
+        let mut set = Self::new(precedence);
+        for path in paths {
+            set.push(BSAArchive::open(path)?);
+        }
+        Ok(set)
+    }
+
+    /// Add an archive after the current members.
+    pub fn push(&mut self, archive: BSAArchive) {
+        self.archives.push(archive);
+    }
+
+    /// Member archives in the order they were added.
+    pub fn archives(&self) -> &[BSAArchive] {
+        &self.archives
+    }
+
+    /// Member indexes in order of precedence, the winning member first.
+    fn members(&self) -> Box<dyn Iterator<Item = usize>> {
+        match self.precedence {
+            Precedence::LastWins => Box::new((0..self.archives.len()).rev()),
+            Precedence::FirstWins => Box::new(0..self.archives.len()),
+        }
+    }
+
+    /// Find a file by path, returning the index of the member providing it.
+    ///
+    /// Unlike lookups within one archive the folder must match as well, a
+    /// file of the same name in another folder of a member is not a match.
+    pub fn get(&self, path: &str) -> Option<(usize, &BSAFile)> {
+        let (folder, name) = path.rsplit_once(['\\', '/']).unwrap_or(("", path));
+        let (folder, name) = (hash_folder(folder), hash_path(name));
+        self.members().find_map(|i| {
+            let file = self.archives[i].file_in(folder, name).filter(|file| file.folder == folder)?;
+            Some((i, file))
+        })
+    }
+
+    /// Extract the contents of a file from the member providing it.
+    pub fn extract(&self, path: &str) -> Result<Vec<u8>> {
+        match self.get(path) {
+            Some((i, file)) => self.archives[i].extract_file(file),
+            None => Err(Error::FileNotFound(path.to_string())),
+        }
+    }
+
+    /// Paths of all named files with the index of the member providing each.
+    ///
+    /// Paths found in several members are listed once, for the winning
+    /// member. Members are listed in order of precedence, each in record order.
+    pub fn paths(&self) -> Vec<(String, usize)> {
+        let mut seen = HashSet::new();
+        let mut paths = Vec::new();
+        for i in self.members() {
+            for path in self.archives[i].paths() {
+                if seen.insert(normalize_path(&path)) {
+                    paths.push((path, i));
+                }
+            }
+        }
+        paths
+    }
+}
+
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    use crate::tests::archive;
+
+    fn set(precedence: Precedence) -> BSAArchiveSet {
+        let mut set = BSAArchiveSet::new(precedence);
+        set.push(BSAParser::from_bytes(archive(&[("a/b.txt", b"first"), ("a/c.txt", b"c")])).parse().unwrap());
+        set.push(BSAParser::from_bytes(archive(&[("a/b.txt", b"second"), ("d/c.txt", b"d")])).parse().unwrap());
+        set
+    }
+
+    #[test]
+    fn last_wins() -> Result<()> {
+        let set = set(Precedence::LastWins);
+        assert_eq!(set.extract("A/B.txt")?, b"second");
+        assert_eq!(set.extract("a/c.txt")?, b"c");
+        assert_eq!(set.get("d/c.txt").map(|(i, _)| i), Some(1));
+        assert!(set.get("e/c.txt").is_none());
+        assert_eq!(set.paths(), [("a\\b.txt".to_string(), 1), ("d\\c.txt".to_string(), 1), ("a\\c.txt".to_string(), 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn first_wins() -> Result<()> {
+        let set = set(Precedence::FirstWins);
+        assert_eq!(set.extract("a/b.txt")?, b"first");
+        assert_eq!(set.paths().len(), 3);
+        assert!(matches!(set.extract("e/c.txt"), Err(Error::FileNotFound(_))));
+        Ok(())
+    }
+}
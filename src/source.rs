@@ -0,0 +1,185 @@
+//! Positional reads of archive data.
+
+use crate::Result;
+
+use std::io::{BufReader, Cursor};
+
+/// Random access source of archive data.
+///
+/// Reads take `&self` and carry their own offset, so an archive never needs
+/// to seek a shared reader and extraction works from a shared reference.
+/// Sources are `Send + Sync`, so an archive can be shared across threads.
+pub trait ArchiveSource: Send + Sync {
+    /// Read exactly `len` bytes starting at `offset`.
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>>;
+
+    /// Read exactly `len` bytes starting at `offset` into `buf`, replacing its
+    /// contents.
+    ///
+    /// Sources held in memory and files reuse the allocation of `buf`, other
+    /// sources fall back to [`ArchiveSource::read_at`].
+    fn read_at_into(&self, offset: u64, len: usize, buf: &mut Vec<u8>) -> Result<()> {
+        *buf = self.read_at(offset, len)?;
+        Ok(())
+    }
+
+    /// Borrow the whole source when it is held in memory.
+    fn bytes(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl ArchiveSource for std::fs::File {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.read_at_into(offset, len, &mut data)?;
+        Ok(data)
+    }
+
+    #[cfg(unix)]
+    fn read_at_into(&self, offset: u64, len: usize, buf: &mut Vec<u8>) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+        buf.clear();
+        buf.resize(len, 0);
+        self.read_exact_at(buf, offset)?;
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn read_at_into(&self, offset: u64, len: usize, buf: &mut Vec<u8>) -> Result<()> {
+        use std::os::windows::fs::FileExt;
+        buf.clear();
+        buf.resize(len, 0);
+        let mut done = 0;
+        while done < len {
+            match self.seek_read(&mut buf[done..], offset + done as u64)? {
+                0 => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+
+    // without positional reads seek the shared cursor, which `&File` allows
+    #[cfg(not(any(unix, windows)))]
+    fn read_at_into(&self, offset: u64, len: usize, buf: &mut Vec<u8>) -> Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+        let mut file = self;
+        buf.clear();
+        buf.resize(len, 0);
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)?;
+        Ok(())
+    }
+}
+
+impl<T: AsRef<[u8]> + Send + Sync> ArchiveSource for Cursor<T> {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.get_ref().as_ref().read_at(offset, len)
+    }
+
+    fn read_at_into(&self, offset: u64, len: usize, buf: &mut Vec<u8>) -> Result<()> {
+        self.get_ref().as_ref().read_at_into(offset, len, buf)
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        Some(self.get_ref().as_ref())
+    }
+}
+
+impl ArchiveSource for &[u8] {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.read_at_into(offset, len, &mut data)?;
+        Ok(data)
+    }
+
+    fn read_at_into(&self, offset: u64, len: usize, buf: &mut Vec<u8>) -> Result<()> {
+        let start = crate::to_usize(offset)?;
+        let end = start.checked_add(len).ok_or(crate::Error::IntegerOverflow)?;
+        match self.get(start..end) {
+            Some(data) => {
+                buf.clear();
+                buf.extend_from_slice(data);
+                Ok(())
+            }
+            None => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        Some(*self)
+    }
+}
+
+// the buffer is bypassed, positional reads go straight to the inner source
+impl<R: ArchiveSource> ArchiveSource for BufReader<R> {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.get_ref().read_at(offset, len)
+    }
+
+    fn read_at_into(&self, offset: u64, len: usize, buf: &mut Vec<u8>) -> Result<()> {
+        self.get_ref().read_at_into(offset, len, buf)
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        self.get_ref().bytes()
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ArchiveSource for memmap2::Mmap {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        (&self[..]).read_at(offset, len)
+    }
+
+    fn read_at_into(&self, offset: u64, len: usize, buf: &mut Vec<u8>) -> Result<()> {
+        (&self[..]).read_at_into(offset, len, buf)
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        Some(&self[..])
+    }
+}
+
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::ArchiveSource;
+
+    #[test]
+    fn cursor_read_at() -> crate::Result<()> {
+        let source = std::io::Cursor::new(b"abcdef".to_vec());
+        assert_eq!(source.read_at(2, 3)?, b"cde");
+        assert_eq!(source.read_at(6, 0)?, b"");
+        assert!(source.read_at(4, 3).is_err());
+        assert!(source.read_at(u64::MAX, 1).is_err());
+        let mut buf = b"xyz".to_vec();
+        source.read_at_into(1, 2, &mut buf)?;
+        assert_eq!(buf, b"bc");
+        Ok(())
+    }
+
+    #[test]
+    fn send_sync() {
+        fn check<T: Send + Sync>() {}
+        check::<crate::BSAArchive>();
+        check::<Box<dyn ArchiveSource>>();
+    }
+
+    #[test]
+    fn file_read_at() -> crate::Result<()> {
+        let path = std::env::temp_dir().join("bsa-parser-read-at.bin");
+        std::fs::write(&path, b"abcdef")?;
+        let source = std::io::BufReader::new(std::fs::File::open(&path)?);
+        assert_eq!(source.read_at(1, 2)?, b"bc");
+        assert!(source.read_at(5, 2).is_err());
+        let mut buf = b"xyz".to_vec();
+        source.read_at_into(3, 3, &mut buf)?;
+        assert_eq!(buf, b"def");
+        assert_eq!(source.bytes(), None);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! String readers for the string encodings used by Bethesda archives.
+
+use std::borrow::Cow;
+use std::ffi::CString;
+use std::io::Read;
+
+use crate::{Error, Result};
+
+/// Read a byte sized string, a `bzstring`.
+///
+/// The string is prefixed by a length byte which counts the trailing nul, the
+/// nul is read but dropped from the returned string. A missing nul fails with
+/// [`Error::MalformedString`].
+pub fn read_bzstring<R: Read>(reader: &mut R) -> Result<CString> {
+    let mut length = [0; 1];
+    reader.read_exact(&mut length)?;
+    let mut v = vec![0; length[0] as usize];
+    reader.read_exact(&mut v)?;
+    // drop the trailing nul, an empty string has none
+    if v.pop().is_some_and(|nul| nul != 0) {
+        return Err(Error::MalformedString);
+    }
+    CString::new(v).map_err(|_| Error::MalformedString)
+}
+
+/// Read a nul terminated string of unknown length, a `zstring`.
+///
+/// Bytes are consumed up to and including the nul, which is dropped from the
+/// returned string. Reaching the end of the reader first fails with
+/// [`Error::UnterminatedString`].
+pub fn read_nul_string<R: Read>(reader: &mut R) -> Result<CString> {
+    let mut v = Vec::new();
+    loop {
+        let mut byte = [0; 1];
+        match reader.read_exact(&mut byte) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Err(Error::UnterminatedString),
+            Err(e) => return Err(e.into()),
+        }
+        if byte[0] == 0 { break; } // terminate at nul byte
+        v.push(byte[0]);
+    }
+    Ok(unsafe { CString::from_vec_unchecked(v) })
+}
+
+/// Characters of Windows-1252 bytes 0x80 to 0x9f, the undefined bytes map to
+/// the matching C1 control characters.
+const CP1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+/// Decode a stored name to a `String`.
+///
+/// The engine stores names in Windows-1252, names are decoded as such unless
+/// they are valid UTF-8, as written by some tools. ASCII names are the same in
+/// either encoding.
+pub fn decode_name(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(name) => name.to_string(),
+        Err(_) => bytes.iter().map(|&b| match b {
+            0x80..=0x9f => CP1252_HIGH[(b - 0x80) as usize],
+            _ => b as char, // ASCII and Latin-1 share their code points
+        }).collect(),
+    }
+}
+
+/// Encode a name for storage and hashing.
+///
+/// Names are encoded in Windows-1252, names with characters outside it are
+/// kept as UTF-8.
+pub fn encode_name(name: &str) -> Cow<'_, [u8]> {
+    if name.is_ascii() {
+        return Cow::Borrowed(name.as_bytes());
+    }
+    let encoded: Option<Vec<u8>> = name.chars().map(|c| match c as u32 {
+        0..=0x7f | 0xa0..=0xff => Some(c as u8),
+        _ => CP1252_HIGH.iter().position(|&high| high == c).map(|i| 0x80 + i as u8),
+    }).collect();
+    match encoded {
+        Some(bytes) => Cow::Owned(bytes),
+        None => Cow::Borrowed(name.as_bytes()),
+    }
+}
+
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn bzstring() -> Result<()> {
+        let mut reader = Cursor::new(b"\x07meshes\0");
+        assert_eq!(read_bzstring(&mut reader)?.as_bytes(), b"meshes");
+        Ok(())
+    }
+
+    #[test]
+    fn bzstring_zero_length() -> Result<()> {
+        let mut reader = Cursor::new(b"\x00\x07meshes\0");
+        assert_eq!(read_bzstring(&mut reader)?.as_bytes(), b"");
+        assert_eq!(read_bzstring(&mut reader)?.as_bytes(), b"meshes");
+        Ok(())
+    }
+
+    #[test]
+    fn bzstring_missing_nul() {
+        let mut reader = Cursor::new(b"\x06meshes\0");
+        assert!(matches!(read_bzstring(&mut reader), Err(Error::MalformedString)));
+    }
+
+    #[test]
+    fn bzstring_truncated() {
+        let mut reader = Cursor::new(b"\x07mesh");
+        assert!(read_bzstring(&mut reader).is_err());
+    }
+
+    #[test]
+    fn nul_string() -> Result<()> {
+        let mut reader = Cursor::new(b"meshes\0a.nif");
+        assert_eq!(read_nul_string(&mut reader)?.as_bytes(), b"meshes");
+        assert!(matches!(read_nul_string(&mut reader), Err(Error::UnterminatedString)));
+        Ok(())
+    }
+
+    #[test]
+    fn windows_1252() {
+        assert_eq!(decode_name(b"caf\xe9 \x80\x8d"), "caf\u{e9} \u{20ac}\u{8d}");
+        assert_eq!(decode_name("caf\u{e9}".as_bytes()), "caf\u{e9}");
+        assert_eq!(encode_name("caf\u{e9} \u{20ac}"), &b"caf\xe9 \x80"[..]);
+        assert_eq!(encode_name("\u{3042}"), "\u{3042}".as_bytes());
+        assert_eq!(decode_name(&encode_name("\u{178}\u{ff}")), "\u{178}\u{ff}");
+    }
+}
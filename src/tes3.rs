@@ -0,0 +1,215 @@
+//! Morrowind archive parser.
+
+use chunk_parser::prelude::*;
+use esm_bindings::bsa::BSAHeader;
+use log::{debug, trace};
+
+use std::ffi::CString;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{
+    capacity, check_bounds, hash_path_bytes, index_files, normalize_bytes, strings, tes4_hash, to_usize,
+    ArchiveFlags, ArchiveSource, BSAArchive, BSAFile, BSAFolder, BSAHashMap, BSAIndex, BSAParser,
+    Error, Result,
+};
+
+impl<R: Read + Seek + ArchiveSource + 'static> BSAParser<R> {
+    /// Parser for the original archive format used in Morrowind.
+    ///
+    /// The format has no folder records, just a flat file list: a 12 byte
+    /// header (version 0x100, hash table offset, file count), the size/offset
+    /// table, the name offset table, the nul delimited name block and finally
+    /// the hash table, followed by file data.
+    ///
+    /// The archive is indexed with TES4 hashes like the later formats, folders
+    /// are synthesised from the directory part of each file path. The header
+    /// is synthesised as a version 0x100 header with folder and file names.
+    pub fn morrowind(mut self) -> Result<BSAArchive> {
+        let version: u32 = self.read()?;
+        if version != 0x100 {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let hash_offset: u32 = self.read()?;
+        let file_count: u32 = self.read()?;
+        let count = to_usize(file_count)?;
+
+        // archive length, restoring the position after the header
+        let length = self.reader().seek(SeekFrom::End(0))?;
+        self.reader().seek(SeekFrom::Start(12))?;
+        let remaining = length.saturating_sub(12);
+
+        let mut records = Vec::with_capacity(capacity(file_count as u64, 8, remaining));
+        for _ in 0..file_count {
+            let record: (u32, u32) = self.at(|p| Ok((p.read()?, p.read()?)))?;
+            records.push(record);
+        }
+
+        let mut name_offsets = Vec::with_capacity(capacity(file_count as u64, 4, remaining));
+        for _ in 0..file_count {
+            name_offsets.push(to_usize(self.at(|p| Ok(p.read::<u32>()?))?)?);
+        }
+
+        // name block fills the space up to the hash table
+        let name_block_size = to_usize(hash_offset)?
+            .checked_sub(12 * count)
+            .ok_or(Error::IntegerOverflow)?;
+        check_bounds(self.reader().stream_position()?, name_block_size as u64, length)?;
+        let mut names = vec![0; name_block_size];
+        self.at(|p| Ok(p.reader().read_exact(&mut names)?))?;
+
+        for _ in 0..file_count {
+            let hash: u64 = self.at(|p| Ok(p.read()?))?;
+            trace!("{:#018x}", hash);
+        }
+
+        // file data offsets are relative to the end of the hash table
+        let data_offset = 12 + hash_offset as u64 + 8 * file_count as u64;
+
+        let mut folders = BSAHashMap::<BSAFolder>::with_kind(self.options.index);
+        let mut entries = Vec::with_capacity(records.len());
+        let mut order = Vec::new();
+        let mut folder_collisions = Vec::new();
+
+        for ((size, offset), name_offset) in records.into_iter().zip(name_offsets) {
+            let name = names.get(name_offset..).ok_or(Error::MalformedString)?;
+            let end = name.iter().position(|&byte| byte == 0).ok_or(Error::MalformedString)?;
+            let path = normalize_bytes(&name[..end]);
+            let (folder_name, file_name) = match path.iter().rposition(|&b| b == b'\\') {
+                Some(i) => (&path[..i], &path[i + 1..]),
+                None => (&[][..], &path[..]),
+            };
+            debug!("{}", strings::decode_name(&path));
+
+            let folder_hash = tes4_hash(folder_name, b"");
+            match folders.get_hash(folder_hash) {
+                Some(folder) => {
+                    // a different name with the same hash merges into the first folder
+                    let same = folder.name.as_ref().is_some_and(|name| name.as_bytes() == folder_name);
+                    if !same && !folder_collisions.contains(&folder_hash) {
+                        folder_collisions.push(folder_hash);
+                    }
+                }
+                None => {
+                    let name = CString::new(folder_name).map_err(|_| Error::MalformedString)?;
+                    folders.insert(folder_hash, BSAFolder { name: Some(name), ..Default::default() });
+                    order.push(folder_hash);
+                }
+            }
+            let hash = hash_path_bytes(file_name);
+            if let Some(folder) = folders.get_hash_mut(folder_hash) {
+                folder.count += 1;
+                folder.files.push(hash);
+            }
+
+            check_bounds(data_offset + offset as u64, size as u64, length)?;
+            let offset = u32::try_from(data_offset + offset as u64).map_err(|_| Error::IntegerOverflow)?;
+            let name = CString::new(file_name).map_err(|_| Error::MalformedString)?;
+            entries.push((hash, BSAFile { size, offset, compressed: false, folder: folder_hash, name: Some(name) }));
+        }
+        let (files, collisions) = index_files(entries, self.options.index);
+
+        let folder_count = u32::try_from(order.len()).map_err(|_| Error::IntegerOverflow)?;
+        let header = synthetic_header(version, folder_count, file_count)?;
+
+        let index = BSAIndex {
+            header, folders, files, order, collisions, folder_collisions, hash_mismatches: Vec::new(),
+            data_base: data_offset, length, lazy: Default::default(), names_skipped: false,
+            names_compressed: false,
+        };
+        Ok(BSAArchive { index, source: Box::new(self.reader) })
+    }
+}
+
+/// Build a later format header for a Morrowind archive, with folder and file
+/// names and no name lengths, by reading it from its serialized bytes.
+fn synthetic_header(version: u32, folder_count: u32, file_count: u32) -> Result<BSAHeader> {
+    let mut data = b"BSA\0".to_vec();
+    let flags = (ArchiveFlags::DIRECTORY_NAMES | ArchiveFlags::FILE_NAMES).bits();
+    let size = std::mem::size_of::<BSAHeader>() as u32;
+    for value in [version, size, flags, folder_count, file_count, 0, 0, 0] {
+        data.extend(value.to_le_bytes());
+    }
+    Ok(BSAParser::from_bytes(data).read()?)
+}
+
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    #[test]
+    fn morrowind() -> crate::Result<()> {
+        let names = b"meshes\\a.nif\0b.txt\0";
+        let mut data = Vec::new();
+        for value in [0x100, 2 * 12 + names.len() as u32, 2, 1, 0, 2, 1, 0, 13] {
+            data.extend(u32::to_le_bytes(value));
+        }
+        data.extend(names);
+        data.extend([0; 16]); // hashes
+        data.extend(b"abb");
+        let data_len = data.len() as u64;
+
+        let bsa = BSAParser::from_bytes(data).morrowind()?;
+        assert_eq!(bsa.file_count(), 2);
+        assert_eq!(bsa.paths(), ["meshes\\a.nif", "b.txt"]);
+        assert_eq!(bsa.extract("meshes/a.nif")?, b"a");
+        assert_eq!(bsa.extract("b.txt")?, b"bb");
+        assert_eq!(bsa.file_data_end(), data_len);
+        Ok(())
+    }
+
+    #[test]
+    fn morrowind_windows_1252() -> crate::Result<()> {
+        let names = b"Caf\xe9\\\xc0.txt\0";
+        let mut data = Vec::new();
+        for value in [0x100, 12 + names.len() as u32, 1, 1, 0, 0] {
+            data.extend(u32::to_le_bytes(value));
+        }
+        data.extend(names);
+        data.extend([0; 8]); // hashes
+        data.extend(b"a");
+
+        let bsa = BSAParser::from_bytes(data).morrowind()?;
+        assert_eq!(bsa.paths(), ["caf\u{e9}\\\u{c0}.txt"]);
+        assert_eq!(bsa.extract("Caf\u{e9}/\u{c0}.txt")?, b"a");
+        Ok(())
+    }
+
+    #[test]
+    fn morrowind_forged() {
+        // version
+        let data = [0x101u32, 0, 0].map(u32::to_le_bytes).concat();
+        assert!(matches!(BSAParser::from_bytes(data).morrowind(), Err(crate::Error::UnsupportedVersion(0x101))));
+
+        // file count
+        let mut data = Vec::new();
+        for value in [0x100, 0, u32::MAX] {
+            data.extend(u32::to_le_bytes(value));
+        }
+        assert!(BSAParser::from_bytes(data).morrowind().is_err());
+
+        // file data past the end of the archive
+        let names = b"a.txt\0";
+        let mut data = Vec::new();
+        for value in [0x100, 12 + names.len() as u32, 1, 2, 0, 0] {
+            data.extend(u32::to_le_bytes(value));
+        }
+        data.extend(names);
+        data.extend([0; 8]); // hashes
+        data.extend(b"a");
+        let result = BSAParser::from_bytes(data).morrowind();
+        assert!(matches!(result, Err(crate::Error::OffsetOutOfBounds { size: 2, .. })));
+    }
+
+    #[test]
+    fn morrowind_empty() -> crate::Result<()> {
+        let mut data = Vec::new();
+        for value in [0x100u32, 0, 0] {
+            data.extend(value.to_le_bytes());
+        }
+        let bsa = BSAParser::from_bytes(data).parse()?;
+        assert_eq!((bsa.folder_count(), bsa.file_count()), (0, 0));
+        Ok(())
+    }
+}
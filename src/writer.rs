@@ -0,0 +1,201 @@
+//! Bethesda Softworks Archive writer.
+
+use std::collections::BTreeMap;
+use std::io::{Seek, Write};
+
+use crate::strings::encode_name;
+use crate::{hash_path_bytes, normalize_bytes, tes4_hash, Error, Result};
+
+/// Folder of files to be written, files are kept sorted by hash.
+#[derive(Default)]
+struct WriterFolder {
+    name: Vec<u8>,
+    files: BTreeMap<u64, (Vec<u8>, Vec<u8>)>,
+}
+
+/// Writer for version 104 archives.
+///
+/// Folder and file records are sorted by hash as the format requires, folder
+/// names are always included, the file name table unless disabled with
+/// [`BSAWriter::with_filenames`], and file data is stored uncompressed.
+pub struct BSAWriter {
+    folders: BTreeMap<u64, WriterFolder>,
+    /// Write the file name table, archive flag 0x2.
+    filenames: bool,
+}
+
+impl Default for BSAWriter {
+    fn default() -> Self {
+        Self { folders: BTreeMap::new(), filenames: true }
+    }
+}
+
+/// Convert a size, offset or count to the u32 stored in the archive.
+fn to_u32(value: usize) -> Result<u32> {
+    u32::try_from(value).map_err(|_| Error::IntegerOverflow)
+}
+
+impl BSAWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Include the file name table and set its archive flag, on by default.
+    ///
+    /// Without it the archive is hash only, files can still be found by path
+    /// as lookups hash the path, but their names cannot be listed.
+    pub fn with_filenames(mut self, filenames: bool) -> Self {
+        self.filenames = filenames;
+        self
+    }
+
+    /// Add a file, replacing any file already added with the same path.
+    ///
+    /// The path is normalized to lowercase with `\` separators, the folder is
+    /// everything before the last separator. Names are stored in Windows-1252
+    /// where possible, see [`encode_name`].
+    pub fn add(&mut self, path: &str, data: Vec<u8>) {
+        let path = normalize_bytes(&encode_name(path));
+        let (folder, name) = match path.iter().rposition(|&b| b == b'\\') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => (&[][..], &path[..]),
+        };
+        let entry = self.folders.entry(tes4_hash(folder, b"")).or_default();
+        entry.name = folder.to_vec();
+        entry.files.insert(hash_path_bytes(name), (name.to_vec(), data));
+    }
+
+    /// Write the archive: header, folder records, folder names with their
+    /// file records, file name table and file data.
+    ///
+    /// Offsets are written relative to the start of `out`.
+    pub fn write_to<W: Write + Seek>(&self, out: &mut W) -> Result<()> {
+        let folder_count = self.folders.len();
+        let file_count: usize = self.folders.values().map(|folder| folder.files.len()).sum();
+        let folder_names_length: usize = self.folders.values().map(|folder| folder.name.len() + 1).sum();
+        let file_names_length: usize = match self.filenames {
+            true => self.folders.values()
+                .flat_map(|folder| folder.files.values())
+                .map(|(name, _)| name.len() + 1)
+                .sum(),
+            false => 0,
+        };
+
+        // layout of the index, file data follows the file name table
+        let header_size = 36;
+        let records_offset = header_size + 16 * folder_count;
+        let data_offset = records_offset + folder_count + folder_names_length + 16 * file_count + file_names_length;
+
+        // header
+        out.write_all(b"BSA\0")?;
+        for value in [
+            104, // version
+            header_size,
+            0x1 | if self.filenames { 0x2 } else { 0 }, // includes directory names, includes filenames
+            folder_count,
+            file_count,
+            folder_names_length,
+            file_names_length,
+            0, // file flags
+        ] {
+            out.write_all(&to_u32(value)?.to_le_bytes())?;
+        }
+
+        // folder records, offsets point at the folder name plus the file name table length
+        let mut block_offset = records_offset;
+        for (&hash, folder) in &self.folders {
+            out.write_all(&hash.to_le_bytes())?;
+            out.write_all(&to_u32(folder.files.len())?.to_le_bytes())?;
+            out.write_all(&to_u32(block_offset + file_names_length)?.to_le_bytes())?;
+            block_offset += folder.name.len() + 2 + 16 * folder.files.len();
+        }
+
+        // folder names and file records
+        let mut file_offset = data_offset;
+        for folder in self.folders.values() {
+            out.write_all(&[u8::try_from(folder.name.len() + 1).map_err(|_| Error::IntegerOverflow)?])?;
+            out.write_all(&folder.name)?;
+            out.write_all(&[0])?;
+            for (&hash, (_, data)) in &folder.files {
+                out.write_all(&hash.to_le_bytes())?;
+                out.write_all(&to_u32(data.len())?.to_le_bytes())?;
+                out.write_all(&to_u32(file_offset)?.to_le_bytes())?;
+                file_offset += data.len();
+            }
+        }
+
+        // file name table
+        if self.filenames {
+            for (name, _) in self.folders.values().flat_map(|folder| folder.files.values()) {
+                out.write_all(name)?;
+                out.write_all(&[0])?;
+            }
+        }
+
+        // file data
+        for (_, data) in self.folders.values().flat_map(|folder| folder.files.values()) {
+            out.write_all(data)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<P: AsRef<str>> FromIterator<(P, Vec<u8>)> for BSAWriter {
+    fn from_iter<I: IntoIterator<Item = (P, Vec<u8>)>>(iter: I) -> Self {
+        let mut writer = BSAWriter::new();
+        for (path, data) in iter {
+            writer.add(path.as_ref(), data);
+        }
+        writer
+    }
+}
+
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn layout() -> Result<()> {
+        let writer: BSAWriter = [
+            ("meshes/b.nif", b"bb".to_vec()),
+            ("meshes/a.nif", b"a".to_vec()),
+            ("textures/c.dds", b"ccc".to_vec()),
+        ].into_iter().collect();
+        let mut out = Cursor::new(Vec::new());
+        writer.write_to(&mut out)?;
+        let data = out.into_inner();
+
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        assert_eq!(&data[..4], b"BSA\0");
+        assert_eq!(u32_at(4), 104);
+        assert_eq!(u32_at(16), 2); // folders
+        assert_eq!(u32_at(20), 3); // files
+        assert_eq!(u32_at(24), 16); // "meshes\0" + "textures\0"
+        assert_eq!(u32_at(28), 18); // "a.nif\0" + "b.nif\0" + "c.dds\0"
+        assert_eq!(data.len(), 36 + 2 * 16 + 2 + 16 + 3 * 16 + 18 + 6);
+        // records sorted by hash: meshes\a.nif, meshes\b.nif, textures\c.dds
+        assert!(data.ends_with(b"a.nif\0b.nif\0c.dds\0abbccc"));
+        Ok(())
+    }
+
+    #[test]
+    fn without_filenames() -> Result<()> {
+        let mut writer = BSAWriter::new().with_filenames(false);
+        writer.add("meshes/a.nif", b"a".to_vec());
+        let mut out = Cursor::new(Vec::new());
+        writer.write_to(&mut out)?;
+        let data = out.into_inner();
+        assert_eq!(data.len(), 36 + 16 + 8 + 16 + 1);
+        assert!(!data.windows(5).any(|name| name == b"a.nif"));
+
+        let bsa = crate::BSAParser::from_bytes(data).v104()?;
+        assert!(!bsa.has_names());
+        assert_eq!(bsa.paths(), Vec::<String>::new());
+        assert_eq!(bsa.extract("meshes/a.nif")?, b"a");
+        Ok(())
+    }
+}